@@ -0,0 +1,302 @@
+/* Control Flow Graph
+ * Splits a program into basic blocks, straight runs of instructions that are
+ * only entered at the top and only left at the bottom. A block starts at the
+ * first instruction, at every label and after every jump, call, return and
+ * halt. Edges record how control moves between blocks. A block that ends in a
+ * call also falls through to the block after it, which is where the call
+ * comes back to, so analyses of one function can follow fallthrough and taken
+ * edges only. Jumps and calls to undefined labels get no edge, the label
+ * checks report those.
+ */
+use std::collections::{BTreeSet, HashSet, VecDeque};
+
+use parsetree::{Instruction, Program};
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub enum EdgeKind {
+	Fallthrough,	/* Into the next block, also from a call to where it comes back */
+	Taken,			/* A goto, or a conditional jump whose condition held */
+	Call,			/* Into the first block of a subroutine */
+	Return,			/* From a return to the block after one of the subroutine's calls */
+	Halt,			/* Out of the program, by halt or by running past its last instruction */
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub enum Node {
+	Block(usize),
+	Exit,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Edge {
+	pub from: usize,
+	pub to: Node,
+	pub kind: EdgeKind,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Block {
+	pub start: usize,	/* Index of the first instruction */
+	pub end: usize,		/* One past the last instruction */
+}
+
+impl Block {
+	pub fn last(&self) -> usize {
+		self.end - 1
+	}
+}
+
+/* The program entry or a subroutine, with every block it reaches without calls */
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Function {
+	pub label: Option<String>,		/* None for the program entry */
+	pub entry: usize,				/* Instruction index it starts at */
+	pub blocks: BTreeSet<usize>,
+}
+
+pub struct Cfg {
+	blocks: Vec<Block>,
+	edges: Vec<Edge>,
+	block_of: Vec<usize>,			/* Block of every instruction */
+	local: Vec<Vec<usize>>,			/* Fallthrough and taken successors of every block */
+	functions: Vec<Function>,		/* Program entry first, then subroutines as calls reach them */
+}
+
+impl Cfg {
+	pub fn build(program: &Program) -> Cfg {
+		let code = program.code();
+
+		let mut leaders: BTreeSet<usize> = BTreeSet::new();
+		leaders.insert(0);
+		for (index, instruction) in code.iter().enumerate() {
+			match *instruction {
+				Instruction::Label(_) => {
+					leaders.insert(index);
+				}
+				Instruction::Goto(_) | Instruction::GoFalse(_) | Instruction::GoTrue(_)
+				| Instruction::Halt | Instruction::Return | Instruction::Call(_) => {
+					leaders.insert(index + 1);
+				}
+				_ => {}
+			}
+		}
+		let starts: Vec<usize> = leaders.into_iter().filter(|&start| start < code.len()).collect();
+		let blocks: Vec<Block> = starts.iter().enumerate().map(|(number, &start)| Block {
+			start,
+			end: starts.get(number + 1).cloned().unwrap_or(code.len()),
+		}).collect();
+		let mut block_of: Vec<usize> = vec![0; code.len()];
+		for (number, block) in blocks.iter().enumerate() {
+			for slot in &mut block_of[block.start..block.end] {
+				*slot = number;
+			}
+		}
+
+		let mut cfg = Cfg { blocks, edges: Vec::new(), block_of, local: Vec::new(), functions: Vec::new() };
+		for number in 0..cfg.blocks.len() {
+			let block = cfg.blocks[number];
+			let next = if block.end < code.len() { Node::Block(number + 1) } else { Node::Exit };
+			let fallthrough = if next == Node::Exit { EdgeKind::Halt } else { EdgeKind::Fallthrough };
+			match code[block.last()] {
+				Instruction::Goto(ref label) => cfg.jump(program, number, label, EdgeKind::Taken),
+				Instruction::GoFalse(ref label) | Instruction::GoTrue(ref label) => {
+					cfg.edges.push(Edge { from: number, to: next, kind: fallthrough });
+					cfg.jump(program, number, label, EdgeKind::Taken);
+				}
+				Instruction::Call(ref label) => {
+					cfg.jump(program, number, label, EdgeKind::Call);
+					cfg.edges.push(Edge { from: number, to: next, kind: fallthrough });
+				}
+				Instruction::Halt => cfg.edges.push(Edge { from: number, to: Node::Exit, kind: EdgeKind::Halt }),
+				Instruction::Return => {}
+				_ => cfg.edges.push(Edge { from: number, to: next, kind: fallthrough }),
+			}
+		}
+
+		cfg.local = (0..cfg.blocks.len()).map(|block| {
+			cfg.successors(block).filter_map(|edge| match (edge.kind, edge.to) {
+				(EdgeKind::Fallthrough, Node::Block(to)) | (EdgeKind::Taken, Node::Block(to)) => Some(to),
+				_ => None,
+			}).collect()
+		}).collect();
+		cfg.find_functions(program);
+		cfg.connect_returns(program);
+		cfg
+	}
+
+	fn jump(&mut self, program: &Program, from: usize, label: &str, kind: EdgeKind) {
+		if let Some(target) = program.target(label) {
+			let to = Node::Block(self.block_of[target]);
+			self.edges.push(Edge { from, to, kind });
+		}
+	}
+
+	/* Subroutines are discovered from the calls of the functions found before them */
+	fn find_functions(&mut self, program: &Program) {
+		let code = program.code();
+		let mut pending: VecDeque<(Option<String>, usize)> = VecDeque::new();
+		let mut seen: HashSet<String> = HashSet::new();
+		pending.push_back((None, 0));
+		while let Some((label, entry)) = pending.pop_front() {
+			let mut blocks: BTreeSet<usize> = BTreeSet::new();
+			let mut worklist: Vec<usize> = Vec::new();
+			if entry < code.len() {
+				worklist.push(self.block_of[entry]);
+			}
+			while let Some(block) = worklist.pop() {
+				if !blocks.insert(block) {
+					continue;
+				}
+				if let Instruction::Call(ref callee) = code[self.blocks[block].last()] {
+					if let Some(target) = program.target(callee) {
+						if seen.insert(callee.clone()) {
+							pending.push_back((Some(callee.clone()), target));
+						}
+					}
+				}
+				worklist.extend(&self.local[block]);
+			}
+			self.functions.push(Function { label, entry, blocks });
+		}
+	}
+
+	/* A return goes back to after every call of the subroutine it is part of */
+	fn connect_returns(&mut self, program: &Program) {
+		let code = program.code();
+		let mut returns: Vec<Edge> = Vec::new();
+		for function in &self.functions {
+			let label = match function.label {
+				Some(ref label) => label,
+				None => continue,
+			};
+			for &block in &function.blocks {
+				if code[self.blocks[block].last()] != Instruction::Return {
+					continue;
+				}
+				for (number, caller) in self.blocks.iter().enumerate() {
+					if code[caller.last()] == Instruction::Call(label.clone()) {
+						let to = if caller.end < code.len() { Node::Block(number + 1) } else { Node::Exit };
+						returns.push(Edge { from: block, to, kind: EdgeKind::Return });
+					}
+				}
+			}
+		}
+		self.edges.extend(returns);
+	}
+
+	pub fn blocks(&self) -> &[Block] {
+		&self.blocks
+	}
+
+	pub fn edges(&self) -> &[Edge] {
+		&self.edges
+	}
+
+	pub fn functions(&self) -> &[Function] {
+		&self.functions
+	}
+
+	pub fn block_of(&self, index: usize) -> usize {
+		self.block_of[index]
+	}
+
+	/* Edges leaving a block */
+	pub fn successors(&self, block: usize) -> impl Iterator<Item = &Edge> {
+		self.edges.iter().filter(move |edge| edge.from == block)
+	}
+
+	/* Edges entering a block */
+	pub fn predecessors(&self, block: usize) -> impl Iterator<Item = &Edge> {
+		self.edges.iter().filter(move |edge| edge.to == Node::Block(block))
+	}
+
+	/* Instructions that can run after `index` in the same function, fallthrough first */
+	pub fn next(&self, index: usize) -> Vec<usize> {
+		let block = self.blocks[self.block_of[index]];
+		if index + 1 < block.end {
+			return vec![index + 1];
+		}
+		/* A call counts as coming back */
+		self.local[self.block_of[index]].iter().map(|&to| self.blocks[to].start).collect()
+	}
+
+	/* Whether the program stops after `index`, by halt or by running past the end */
+	pub fn exits(&self, index: usize) -> bool {
+		let block = self.block_of[index];
+		index == self.blocks[block].last() && self.successors(block).any(|edge| edge.kind == EdgeKind::Halt)
+	}
+
+	/* Graphviz rendering, one cluster per function and the instructions in every block */
+	pub fn to_dot(&self, program: &Program, name: &str) -> String {
+		let code = program.code();
+		let mut out = String::new();
+		out.push_str(&format!("digraph {} {{\n", quote(name)));
+		out.push_str("\tnode [shape=box, fontname=\"monospace\"];\n");
+		out.push_str("\texit [shape=doublecircle, label=\"exit\"];\n");
+
+		let node = |block: usize| -> String {
+			let block = &self.blocks[block];
+			/* \\l ends a left aligned line */
+			let text: String = (block.start..block.end)
+				.map(|index| format!("{}\\l", escape(&format!("{}: {}", index, code[index]))))
+				.collect();
+			format!("b{} [label=\"{}\"];\n", block.start, text)
+		};
+
+		/* A block reached from several functions is drawn in the first one */
+		let mut drawn: HashSet<usize> = HashSet::new();
+		for (number, function) in self.functions.iter().enumerate() {
+			let title = match function.label {
+				Some(ref label) => label.clone(),
+				None => "program entry".to_string(),
+			};
+			out.push_str(&format!("\tsubgraph cluster_{} {{\n\t\tlabel={};\n", number, quote(&title)));
+			for &block in &function.blocks {
+				if drawn.insert(block) {
+					out.push_str(&format!("\t\t{}", node(block)));
+				}
+			}
+			out.push_str("\t}\n");
+		}
+		/* Blocks no function reaches */
+		for block in 0..self.blocks.len() {
+			if drawn.insert(block) {
+				out.push_str(&format!("\t{}", node(block).replace("];", ", color=gray, fontcolor=gray];")));
+			}
+		}
+
+		for edge in &self.edges {
+			let to = match edge.to {
+				Node::Block(block) => format!("b{}", self.blocks[block].start),
+				Node::Exit => "exit".to_string(),
+			};
+			let style = match edge.kind {
+				EdgeKind::Fallthrough => "",
+				EdgeKind::Taken => " [label=\"taken\"]",
+				EdgeKind::Call => " [label=\"call\", style=dashed]",
+				EdgeKind::Return => " [label=\"return\", style=dotted]",
+				EdgeKind::Halt => " [label=\"halt\"]",
+			};
+			out.push_str(&format!("\tb{} -> {}{};\n", self.blocks[edge.from].start, to, style));
+		}
+		out.push_str("}\n");
+		out
+	}
+}
+
+/* A DOT string literal */
+fn quote(text: &str) -> String {
+	format!("\"{}\"", escape(text))
+}
+
+fn escape(text: &str) -> String {
+	let mut escaped = String::new();
+	for c in text.chars() {
+		match c {
+			'"' => escaped.push_str("\\\""),
+			'\\' => escaped.push_str("\\\\"),
+			_ => escaped.push(c),
+		}
+	}
+	escaped
+}
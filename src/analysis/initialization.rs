@@ -0,0 +1,196 @@
+/* Initialization
+ * Finds every rvalue that can read a variable before any := has stored to it
+ * on some path. Variables start at zero, so such a read runs, but it is
+ * usually a typo or a missing assignment. What is assigned is tracked per
+ * frame (see scope): a begin block's frame starts out empty, a subroutine
+ * starts out with what all of its calls have assigned in the frame it runs
+ * in, and a call gets back what all of the subroutine's returns have assigned.
+ * Those summaries only shrink from one round to the next, so recursion settles.
+ */
+use std::collections::{BTreeMap, BTreeSet, HashMap};
+
+use analysis::cfg::{Cfg, Function};
+use analysis::scope::{call_frame, resolve, Frame, Phase, Scope, Variable};
+use analysis::stack::{step, Shape};
+use diagnostics::Diagnostic;
+use parsetree::{Instruction, Program};
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum InitError {
+	ReadBeforeAssign(String, usize),	/* Variable name, rvalue that reads it */
+}
+
+impl InitError {
+	pub fn index(&self) -> usize {
+		match *self {
+			InitError::ReadBeforeAssign(_, index) => index,
+		}
+	}
+
+	/* The read gets zero, which is well defined */
+	pub fn is_error(&self) -> bool {
+		false
+	}
+
+	pub fn diagnostic(&self, program: &Program) -> Diagnostic {
+		match *self {
+			InitError::ReadBeforeAssign(ref name, index) => {
+				Diagnostic::warning(format!("variable `{}` may be read before it is assigned", name))
+					.at(program.span(index))
+					.label("reads 0 on at least one path")
+					.help(format!("store a value first with `lvalue {}`, a value and `:=`", name))
+			}
+		}
+	}
+}
+
+/* Names of a subroutine's own frame that are assigned, None until a call or return is seen */
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+struct Summary {
+	entry: Option<BTreeSet<String>>,
+	exit: Option<BTreeSet<String>>,
+}
+
+/* What one pass over a function found */
+struct Walk {
+	reads: BTreeSet<usize>,							/* rvalues that can read an unassigned variable */
+	calls: Vec<(String, BTreeSet<String>)>,			/* Callees, and what the frame they run in has assigned */
+	exit: Option<BTreeSet<String>>,					/* Own frame names assigned at every return */
+}
+
+/* Reads before assignment, in source order */
+pub fn check(program: &Program) -> Vec<InitError> {
+	let cfg = Cfg::build(program);
+	let mut summaries: HashMap<String, Summary> = HashMap::new();
+	loop {
+		let mut walks: Vec<(&Function, Walk)> = Vec::new();
+		for function in cfg.functions() {
+			let entry = match function.label {
+				None => Some(BTreeSet::new()),
+				Some(ref label) => summaries.get(label).and_then(|summary| summary.entry.clone()),
+			};
+			/* A subroutine no analysed call reaches yet has nothing to report */
+			if let Some(entry) = entry {
+				walks.push((function, walk(program, &cfg, function, &entry, &summaries)));
+			}
+		}
+
+		let mut next: HashMap<String, Summary> = HashMap::new();
+		for (function, walk) in &walks {
+			if let Some(ref label) = function.label {
+				next.entry(label.clone()).or_default().exit = walk.exit.clone();
+			}
+			for (callee, assigned) in &walk.calls {
+				let entry = &mut next.entry(callee.clone()).or_default().entry;
+				*entry = Some(match entry.take() {
+					Some(known) => known.intersection(assigned).cloned().collect(),
+					None => assigned.clone(),
+				});
+			}
+		}
+
+		if next == summaries {
+			return walks.iter()
+				.flat_map(|(_, walk)| walk.reads.iter().cloned())
+				.collect::<BTreeSet<usize>>()
+				.into_iter()
+				.filter_map(|index| match program.code()[index] {
+					Instruction::Rvalue(ref name) => Some(InitError::ReadBeforeAssign(name.clone(), index)),
+					_ => None,
+				})
+				.collect();
+		}
+		summaries = next;
+	}
+}
+
+/* Before an instruction: the stack, with the variable of every address on it, the open
+ * begin blocks, and the variables assigned on every path there.
+ */
+type State = (Vec<Shape>, Scope, BTreeSet<Variable>);
+
+fn walk(program: &Program, cfg: &Cfg, function: &Function, entry: &BTreeSet<String>, summaries: &HashMap<String, Summary>) -> Walk {
+	let code = program.code();
+	let mut result = Walk { reads: BTreeSet::new(), calls: Vec::new(), exit: None };
+	let assigned: BTreeSet<Variable> = entry.iter().map(|name| Variable::new(Frame::Own, name)).collect();
+
+	/* Paths meeting keep what both assigned, an instruction is looked at again whenever that shrinks */
+	let mut states: BTreeMap<usize, State> = BTreeMap::new();
+	let mut worklist: Vec<(usize, State)> = Vec::new();
+	if function.entry < code.len() {
+		worklist.push((function.entry, (Vec::new(), Vec::new(), assigned)));
+	}
+	while let Some((index, state)) = worklist.pop() {
+		let (mut stack, mut scope, mut assigned) = match states.get(&index) {
+			Some(known) => {
+				let narrowed: BTreeSet<Variable> = known.2.intersection(&state.2).cloned().collect();
+				if narrowed == known.2 {
+					continue;
+				}
+				(known.0.clone(), known.1.clone(), narrowed)
+			}
+			None => state,
+		};
+		states.insert(index, (stack.clone(), scope.clone(), assigned.clone()));
+
+		/* := stores to the lvalue below its value. The stack verifier has reported any
+		 * pop that fails, such a path is not followed here.
+		 */
+		let target = match stack.len().checked_sub(2).map(|below| &stack[below]) {
+			Some(Shape::Address(variable)) if code[index] == Instruction::Assign => Some(variable.clone()),
+			_ => None,
+		};
+		if step(&code[index], index, &scope, &mut stack).is_err() {
+			continue;
+		}
+		match code[index] {
+			Instruction::Rvalue(ref name) if !assigned.contains(&Variable::new(resolve(&scope, false), name)) => {
+				result.reads.insert(index);
+			}
+			Instruction::Assign => assigned.extend(target),
+			/* Every call gets a fresh frame */
+			Instruction::Begin => {
+				assigned.retain(|variable| variable.frame != Frame::Block(index));
+				scope.push((index, Phase::Setup));
+			}
+			Instruction::End => {
+				if let Some((begin, _)) = scope.pop() {
+					assigned.retain(|variable| variable.frame != Frame::Block(begin));
+				}
+			}
+			Instruction::Call(ref callee) => {
+				let frame = call_frame(&scope);
+				let handed: BTreeSet<String> = assigned.iter()
+					.filter(|variable| variable.frame == frame)
+					.map(|variable| variable.name.clone())
+					.collect();
+				result.calls.push((callee.clone(), handed));
+
+				/* Until the subroutine is seen to return, nothing after the call is known to run */
+				match summaries.get(callee).and_then(|summary| summary.exit.as_ref()) {
+					Some(exit) => assigned.extend(exit.iter().map(|name| Variable::new(frame, name))),
+					None => continue,
+				}
+				if let Some(block) = scope.last_mut() {
+					block.1 = Phase::Returned;
+				}
+			}
+			Instruction::Return => {
+				let own: BTreeSet<String> = assigned.iter()
+					.filter(|variable| variable.frame == Frame::Own)
+					.map(|variable| variable.name.clone())
+					.collect();
+				result.exit = Some(match result.exit.take() {
+					Some(known) => known.intersection(&own).cloned().collect(),
+					None => own,
+				});
+			}
+			_ => {}
+		}
+
+		for next in cfg.next(index) {
+			worklist.push((next, (stack.clone(), scope.clone(), assigned.clone())));
+		}
+	}
+	result
+}
@@ -0,0 +1,238 @@
+/* Label Resolution
+ * Builds a table of every label with where it is defined, jumped to and
+ * called from. Numeric labels (label 2000) are plain jump targets, named
+ * labels are what subroutines are called by, and the table keeps the two apart.
+ */
+use std::collections::BTreeMap;
+
+use diagnostics::Diagnostic;
+use parsetree::{Instruction, Program};
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum LabelKind {
+	Numeric,
+	Named,
+}
+
+impl LabelKind {
+	pub fn of(name: &str) -> LabelKind {
+		if !name.is_empty() && name.chars().all(|c| c.is_ascii_digit()) {
+			LabelKind::Numeric
+		} else {
+			LabelKind::Named
+		}
+	}
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Label {
+	pub kind: LabelKind,
+	pub definitions: Vec<usize>,	/* Index of every `label` instruction with this name */
+	pub jumps: Vec<usize>,			/* goto, gofalse and gotrue instructions that target it */
+	pub calls: Vec<usize>,			/* call instructions that target it */
+}
+
+impl Label {
+	fn new(name: &str) -> Label {
+		Label { kind: LabelKind::of(name), definitions: Vec::new(), jumps: Vec::new(), calls: Vec::new() }
+	}
+
+	/* Where jumps and calls continue, the first definition wins when there are several */
+	pub fn target(&self) -> Option<usize> {
+		self.definitions.first().cloned()
+	}
+}
+
+/* Every label the program mentions, sorted by name */
+pub type LabelTable = BTreeMap<String, Label>;
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum LabelError {
+	Undefined(String, usize),			/* Label name, jump or call that uses it */
+	Duplicate(String, usize, usize),	/* Label name, first definition, redefinition */
+	Unused(String, usize),				/* Label name, its definition */
+	CalledAndJumpedTo(String, usize),	/* Label name, first jump into the subroutine */
+}
+
+impl LabelError {
+	pub fn index(&self) -> usize {
+		match *self {
+			LabelError::Undefined(_, index)
+			| LabelError::Duplicate(_, _, index)
+			| LabelError::Unused(_, index)
+			| LabelError::CalledAndJumpedTo(_, index) => index,
+		}
+	}
+
+	/* Labels nobody jumps to and jumps into subroutines still run, the rest rejects the program */
+	pub fn is_error(&self) -> bool {
+		matches!(*self, LabelError::Undefined(..) | LabelError::Duplicate(..))
+	}
+
+	pub fn diagnostic(&self, program: &Program) -> Diagnostic {
+		let span = program.span(self.index());
+		match *self {
+			LabelError::Undefined(ref name, _) => {
+				let diagnostic = Diagnostic::error(format!("undefined label `{}`", name))
+					.at(span)
+					.label("no `label` with this name");
+				match closest(program, name) {
+					Some(candidate) => diagnostic.help(format!("a label with a similar name exists: `{}`", candidate)),
+					None => diagnostic,
+				}
+			}
+			LabelError::Duplicate(ref name, first, _) => Diagnostic::error(format!("label `{}` is defined more than once", name))
+				.at(span)
+				.label("redefined here")
+				.note(format!("first defined at {}", program.span(first)))
+				.help("rename one of the labels"),
+			LabelError::Unused(ref name, _) => {
+				let what = match LabelKind::of(name) {
+					LabelKind::Numeric => "numeric label",
+					LabelKind::Named => "label",
+				};
+				Diagnostic::warning(format!("{} `{}` is never jumped to or called", what, name)).at(span)
+			}
+			LabelError::CalledAndJumpedTo(ref name, _) => Diagnostic::warning(format!("subroutine `{}` is also the target of a jump", name))
+				.at(span)
+				.label("jumps into the subroutine")
+				.note("a `return` reached this way has no call to go back to"),
+		}
+	}
+}
+
+/* Resolves every jump and call, problems come back in source order */
+pub fn resolve(program: &Program) -> (LabelTable, Vec<LabelError>) {
+	let mut table: LabelTable = BTreeMap::new();
+	for (index, instruction) in program.code().iter().enumerate() {
+		match *instruction {
+			Instruction::Label(ref name) => table.entry(name.clone()).or_insert_with(|| Label::new(name)).definitions.push(index),
+			Instruction::Goto(ref name) | Instruction::GoFalse(ref name) | Instruction::GoTrue(ref name) => {
+				table.entry(name.clone()).or_insert_with(|| Label::new(name)).jumps.push(index)
+			}
+			Instruction::Call(ref name) => table.entry(name.clone()).or_insert_with(|| Label::new(name)).calls.push(index),
+			_ => {}
+		}
+	}
+
+	let mut problems: Vec<LabelError> = Vec::new();
+	for (name, label) in &table {
+		match label.target() {
+			None => {
+				for &index in label.jumps.iter().chain(&label.calls) {
+					problems.push(LabelError::Undefined(name.clone(), index));
+				}
+			}
+			Some(first) => {
+				for &again in &label.definitions[1..] {
+					problems.push(LabelError::Duplicate(name.clone(), first, again));
+				}
+				if label.jumps.is_empty() && label.calls.is_empty() {
+					problems.push(LabelError::Unused(name.clone(), first));
+				}
+				if !label.calls.is_empty() && !label.jumps.is_empty() {
+					problems.push(LabelError::CalledAndJumpedTo(name.clone(), label.jumps[0]));
+				}
+			}
+		}
+	}
+
+	problems.sort_by_key(|problem| problem.index());
+	(table, problems)
+}
+
+/* A defined label within two edits of `name`, for typo suggestions */
+fn closest(program: &Program, name: &str) -> Option<String> {
+	let mut labels: Vec<&String> = program.labels().keys().collect();
+	labels.sort();
+	labels.into_iter()
+		.map(|label| (distance(label, name), label))
+		.filter(|&(distance, _)| distance <= 2)
+		.min_by_key(|&(distance, _)| distance)
+		.map(|(_, label)| label.clone())
+}
+
+/* Levenshtein distance */
+fn distance(a: &str, b: &str) -> usize {
+	let b: Vec<char> = b.chars().collect();
+	let mut row: Vec<usize> = (0..=b.len()).collect();
+	for (i, ca) in a.chars().enumerate() {
+		let mut diagonal = row[0];
+		row[0] = i + 1;
+		for (j, &cb) in b.iter().enumerate() {
+			let above = row[j + 1];
+			row[j + 1] = if ca == cb { diagonal } else { 1 + diagonal.min(above).min(row[j]) };
+			diagonal = above;
+		}
+	}
+	row[b.len()]
+}
+
+#[cfg(test)]
+mod tests {
+	use super::{closest, distance, resolve, LabelError, LabelKind};
+	use parsetree::Program;
+
+	fn program(source: &str) -> Program {
+		::parse(source).unwrap()
+	}
+
+	fn problems(source: &str) -> Vec<LabelError> {
+		resolve(&program(source)).1
+	}
+
+	#[test]
+	fn resolves_jumps_and_calls() {
+		let (table, found) = resolve(&program("goto 10\nlabel 10\ncall work\nhalt\nlabel work\nreturn\n"));
+		assert_eq!(found, vec![]);
+		assert_eq!(table["10"].kind, LabelKind::Numeric);
+		assert_eq!((table["10"].target(), table["10"].jumps.clone()), (Some(1), vec![0]));
+		assert_eq!(table["work"].kind, LabelKind::Named);
+		assert_eq!((table["work"].target(), table["work"].calls.clone()), (Some(4), vec![2]));
+	}
+
+	#[test]
+	fn reports_undefined_labels() {
+		let found = problems("goto nowhere\ncall missing\ngofalse nowhere\n");
+		assert_eq!(found, vec![
+			LabelError::Undefined("nowhere".to_string(), 0),
+			LabelError::Undefined("missing".to_string(), 1),
+			LabelError::Undefined("nowhere".to_string(), 2),
+		]);
+		assert!(found.iter().all(|problem| problem.is_error()));
+	}
+
+	#[test]
+	fn reports_duplicate_labels() {
+		let found = problems("label a\ngoto a\nlabel a\nlabel a\n");
+		assert_eq!(found, vec![LabelError::Duplicate("a".to_string(), 0, 2), LabelError::Duplicate("a".to_string(), 0, 3)]);
+		assert!(found[0].is_error());
+	}
+
+	#[test]
+	fn warns_about_unused_labels_and_jumps_into_subroutines() {
+		let found = problems("label 10\nhalt\n");
+		assert_eq!(found, vec![LabelError::Unused("10".to_string(), 0)]);
+		assert!(!found[0].is_error());
+
+		let found = problems("call work\ngoto work\nlabel work\nreturn\n");
+		assert_eq!(found, vec![LabelError::CalledAndJumpedTo("work".to_string(), 1)]);
+		assert!(!found[0].is_error());
+	}
+
+	#[test]
+	fn suggests_labels_with_similar_names() {
+		assert_eq!(distance("loop", "loop"), 0);
+		assert_eq!(distance("loop", "lop"), 1);
+		assert_eq!(distance("kitten", "sitting"), 3);
+		assert_eq!(distance("", "abc"), 3);
+
+		let program = program("goto lop\nlabel loop\nlabel done\nhalt\n");
+		assert_eq!(closest(&program, "lop"), Some("loop".to_string()));
+		assert_eq!(closest(&program, "dome"), Some("done".to_string()));
+		assert_eq!(closest(&program, "elsewhere"), None);
+
+		let diagnostic = resolve(&program).1[0].diagnostic(&program);
+		assert_eq!(diagnostic.help, vec!["a label with a similar name exists: `loop`".to_string()]);
+	}
+}
@@ -0,0 +1,12 @@
+/* Static Analysis
+ * Passes that check a parsed program before a backend lowers it. Each pass
+ * returns every problem it finds, and each problem can be rendered as a
+ * diagnostic pointing at the offending instruction. The control flow graph
+ * the passes and the backends walk lives here too.
+ */
+pub mod cfg;
+pub mod initialization;
+pub mod labels;
+pub mod reachability;
+pub mod scope;
+pub mod stack;
@@ -0,0 +1,112 @@
+/* Reachability
+ * Finds the instructions no run of the program gets to. The program entry and
+ * every subroutine called from code that runs are the roots, so what is left
+ * is code after a halt, goto or return that no jump lands in, and subroutines
+ * nobody calls. Unreachable code is reported in runs, and a run that starts at
+ * a subroutine's label is reported as that subroutine instead.
+ */
+use diagnostics::Diagnostic;
+use analysis::cfg::Cfg;
+use analysis::labels::{LabelKind, LabelTable};
+use parsetree::{Instruction, Program};
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum ReachError {
+	Unreachable(usize, usize),								/* First and last instruction of a run that never runs */
+	UnusedSubroutine(String, usize, usize, Option<usize>),	/* Label name, its definition, last instruction, a call from unreachable code */
+}
+
+impl ReachError {
+	pub fn index(&self) -> usize {
+		match *self {
+			ReachError::Unreachable(index, _) | ReachError::UnusedSubroutine(_, index, _, _) => index,
+		}
+	}
+
+	/* Code that never runs does no harm */
+	pub fn is_error(&self) -> bool {
+		false
+	}
+
+	pub fn diagnostic(&self, program: &Program) -> Diagnostic {
+		let span = program.span(self.index());
+		match *self {
+			ReachError::Unreachable(first, last) => Diagnostic::warning("unreachable code")
+				.at(span)
+				.label(extent(program, first, last))
+				.note("code after `halt`, `goto` or `return` only runs when a jump lands on a label in front of it"),
+			ReachError::UnusedSubroutine(ref name, first, last, None) => Diagnostic::warning(format!("subroutine `{}` is never called", name))
+				.at(span)
+				.label(extent(program, first, last)),
+			ReachError::UnusedSubroutine(ref name, first, last, Some(call)) => {
+				Diagnostic::warning(format!("subroutine `{}` is only called from unreachable code", name))
+					.at(span)
+					.label(extent(program, first, last))
+					.note(format!("called at {}", program.span(call)))
+			}
+		}
+	}
+}
+
+/* What the caret under the first instruction of a run says */
+fn extent(program: &Program, first: usize, last: usize) -> String {
+	if first == last {
+		"never runs".to_string()
+	} else {
+		format!("{} instructions up to line {} never run", last - first + 1, program.span(last).line)
+	}
+}
+
+/* Whether each instruction can run */
+pub fn reachable(program: &Program, cfg: &Cfg) -> Vec<bool> {
+	let mut reached = vec![false; program.code().len()];
+	for function in cfg.functions() {
+		for &block in &function.blocks {
+			let block = cfg.blocks()[block];
+			for slot in &mut reached[block.start..block.end] {
+				*slot = true;
+			}
+		}
+	}
+	reached
+}
+
+/* Runs of unreachable code, in source order */
+pub fn check(program: &Program, table: &LabelTable) -> Vec<ReachError> {
+	let code = program.code();
+	let reached = reachable(program, &Cfg::build(program));
+
+	let mut problems: Vec<ReachError> = Vec::new();
+	let mut index = 0;
+	while index < code.len() {
+		if reached[index] {
+			index += 1;
+			continue;
+		}
+		/* A run ends where code runs again or the next subroutine starts */
+		let first = index;
+		index += 1;
+		while index < code.len() && !reached[index] && subroutine(&code[index], table).is_none() {
+			index += 1;
+		}
+		problems.push(match subroutine(&code[first], table) {
+			Some(name) => {
+				let call = table.get(name).and_then(|label| label.calls.first().cloned());
+				ReachError::UnusedSubroutine(name.to_string(), first, index - 1, call)
+			}
+			None => ReachError::Unreachable(first, index - 1),
+		});
+	}
+	problems
+}
+
+/* The name of the subroutine a named label starts, unless only jumps go there */
+fn subroutine<'a>(instruction: &'a Instruction, table: &LabelTable) -> Option<&'a str> {
+	match *instruction {
+		Instruction::Label(ref name) if LabelKind::of(name) == LabelKind::Named => {
+			let jumped_to = table.get(name).is_some_and(|label| label.calls.is_empty() && !label.jumps.is_empty());
+			if jumped_to { None } else { Some(name) }
+		}
+		_ => None,
+	}
+}
@@ -0,0 +1,52 @@
+/* Scoping
+ * Which activation record lvalue and rvalue name a variable in, following the
+ * rules the interpreter implements. `begin` opens a frame for a call, lvalue
+ * writes into it and rvalue reads from the enclosing one until the `call`;
+ * after the `return` it is the other way round, until `end` closes it. A
+ * subroutine has no frame of its own, it runs in whichever frame it is called
+ * in, so the same instruction can name different frames at different calls.
+ */
+
+/* Which activation record a variable lives in, as seen from one function */
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub enum Frame {
+	Own,			/* The function's own frame */
+	Block(usize),	/* Frame opened by the begin at this instruction index */
+}
+
+#[derive(Clone, Debug, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub struct Variable {
+	pub frame: Frame,
+	pub name: String,
+}
+
+impl Variable {
+	pub fn new(frame: Frame, name: &str) -> Variable {
+		Variable { frame, name: name.to_string() }
+	}
+}
+
+/* How far the call of an open begin block has got */
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Phase {
+	Setup,		/* Between begin and call */
+	Returned,	/* Between return and end */
+}
+
+/* Begin blocks a function has open at an instruction, innermost last */
+pub type Scope = Vec<(usize, Phase)>;
+
+/* The frame lvalue (or rvalue) refers to inside `scope` */
+pub fn resolve(scope: &Scope, lvalue: bool) -> Frame {
+	let enclosing = |depth: usize| if depth == 0 { Frame::Own } else { Frame::Block(scope[depth - 1].0) };
+	match scope.last() {
+		None => Frame::Own,
+		Some(&(begin, Phase::Setup)) => if lvalue { Frame::Block(begin) } else { enclosing(scope.len() - 1) },
+		Some(&(begin, Phase::Returned)) => if lvalue { enclosing(scope.len() - 1) } else { Frame::Block(begin) },
+	}
+}
+
+/* A call runs in the frame of the innermost open begin block, or shares the caller's */
+pub fn call_frame(scope: &Scope) -> Frame {
+	scope.last().map_or(Frame::Own, |&(begin, _)| Frame::Block(begin))
+}
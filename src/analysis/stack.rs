@@ -0,0 +1,299 @@
+/* Stack Verifier
+ * Computes the stack before every instruction along every control flow path.
+ * The program entry starts with an empty stack; every `call` target is checked
+ * as a subroutine starting from an empty stack of its own, and has to return
+ * with the stack as it found it, so a call leaves its caller's stack alone.
+ * An address remembers the variable it names, so paths only agree on a stack
+ * when they would assign to the same variables. Code generation lowers the
+ * stack with the same model, see codegen::lower.
+ */
+use std::collections::{BTreeMap, BTreeSet};
+
+use analysis::cfg::Cfg;
+use analysis::scope::{resolve, Phase, Scope, Variable};
+use diagnostics::Diagnostic;
+use parsetree::{Instruction, Program};
+
+/* What is known about a stack entry before the program runs */
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum Shape {
+	Value,
+	Address(Variable),	/* Pushed by lvalue, only := may consume it */
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum StackError {
+	Underflow(usize),					/* Instruction index that pops an empty stack */
+	ExpectedInteger(usize),				/* An address is used as a number */
+	ExpectedAddress(usize),				/* := without an lvalue below the value */
+	DepthMismatch(usize, usize, usize),	/* Join point, depth on one path, depth on another */
+	ShapeMismatch(usize),				/* Join point reached with different lvalues, or an lvalue and a value */
+	LeftoverAtHalt(usize, usize),		/* Values still on the stack when the program stops */
+	UnbalancedReturn(usize, usize),		/* Subroutine returns with this many extra values */
+	ReturnOutsideSubroutine(usize),
+}
+
+impl StackError {
+	pub fn index(&self) -> usize {
+		match *self {
+			StackError::Underflow(index)
+			| StackError::ExpectedInteger(index)
+			| StackError::ExpectedAddress(index)
+			| StackError::DepthMismatch(index, _, _)
+			| StackError::ShapeMismatch(index)
+			| StackError::LeftoverAtHalt(index, _)
+			| StackError::UnbalancedReturn(index, _)
+			| StackError::ReturnOutsideSubroutine(index) => index,
+		}
+	}
+
+	/* Leftover values are harmless to run, every other problem rejects the program */
+	pub fn is_error(&self) -> bool {
+		!matches!(*self, StackError::LeftoverAtHalt(..))
+	}
+
+	pub fn diagnostic(&self, program: &Program) -> Diagnostic {
+		let span = program.span(self.index());
+		match *self {
+			StackError::Underflow(_) => Diagnostic::error("stack underflow").at(span).label("pops an empty stack"),
+			StackError::ExpectedInteger(_) => Diagnostic::error("expected an integer on the stack")
+				.at(span)
+				.label("finds an lvalue here")
+				.help("use `rvalue` to push the contents of a variable"),
+			StackError::ExpectedAddress(_) => Diagnostic::error("expected an lvalue on the stack")
+				.at(span)
+				.label("nothing to assign to")
+				.note("`:=` stores the top of the stack into the `lvalue` below it"),
+			StackError::DepthMismatch(_, first, second) => Diagnostic::error("paths reach this instruction with different stack depths")
+				.at(span)
+				.label(format!("reached with {} and with {} values on the stack", first.min(second), first.max(second)))
+				.note("every jump to a label must leave the stack as deep as falling through to it does"),
+			StackError::ShapeMismatch(_) => Diagnostic::error("paths reach this instruction with different stacks")
+				.at(span)
+				.label("the paths leave different lvalues, or an lvalue and a value, on the stack")
+				.note("`:=` has to store to the same variable whichever way it is reached"),
+			StackError::LeftoverAtHalt(_, depth) => Diagnostic::warning(format!("{} value{} left on the stack when the program stops", depth, plural(depth)))
+				.at(span)
+				.help("`pop` values that are no longer needed, `print` does not remove the value it writes"),
+			StackError::UnbalancedReturn(_, depth) => Diagnostic::error("subroutine does not leave the stack as it found it")
+				.at(span)
+				.label(format!("returns with {} extra value{}", depth, plural(depth)))
+				.note("subroutines communicate through variables, not the stack"),
+			StackError::ReturnOutsideSubroutine(_) => Diagnostic::error("return outside of a subroutine")
+				.at(span)
+				.label("reached without a `call`"),
+		}
+	}
+}
+
+fn plural(count: usize) -> &'static str {
+	if count == 1 { "" } else { "s" }
+}
+
+/* Checks the program entry and every subroutine, problems come back in source order */
+pub fn verify(program: &Program) -> Vec<StackError> {
+	let mut problems: BTreeSet<(usize, StackErrorKey)> = BTreeSet::new();
+	let mut found: Vec<StackError> = Vec::new();
+
+	let cfg = Cfg::build(program);
+	for function in cfg.functions() {
+		for problem in verify_function(program, &cfg, function.label.is_some(), function.entry) {
+			if problems.insert((problem.index(), StackErrorKey::of(&problem))) {
+				found.push(problem);
+			}
+		}
+	}
+
+	found.sort_by_key(|problem| problem.index());
+	found
+}
+
+/* The same instruction can be reported from several subroutines, only keep one */
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord)]
+enum StackErrorKey {
+	Underflow,
+	ExpectedInteger,
+	ExpectedAddress,
+	Mismatch,
+	LeftoverAtHalt,
+	UnbalancedReturn,
+	ReturnOutsideSubroutine,
+}
+
+impl StackErrorKey {
+	fn of(problem: &StackError) -> StackErrorKey {
+		match *problem {
+			StackError::Underflow(_) => StackErrorKey::Underflow,
+			StackError::ExpectedInteger(_) => StackErrorKey::ExpectedInteger,
+			StackError::ExpectedAddress(_) => StackErrorKey::ExpectedAddress,
+			StackError::DepthMismatch(..) | StackError::ShapeMismatch(_) => StackErrorKey::Mismatch,
+			StackError::LeftoverAtHalt(..) => StackErrorKey::LeftoverAtHalt,
+			StackError::UnbalancedReturn(..) => StackErrorKey::UnbalancedReturn,
+			StackError::ReturnOutsideSubroutine(_) => StackErrorKey::ReturnOutsideSubroutine,
+		}
+	}
+}
+
+fn verify_function(program: &Program, cfg: &Cfg, subroutine: bool, entry: usize) -> Vec<StackError> {
+	let code = program.code();
+	let mut problems: Vec<StackError> = Vec::new();
+	if code.is_empty() {
+		return problems;
+	}
+
+	/* Stack before every reachable instruction, a path stops at its first problem.
+	 * The open begin blocks decide which variable an lvalue names; paths reaching
+	 * an instruction inside different blocks are for code generation to reject.
+	 */
+	let mut stacks: BTreeMap<usize, Vec<Shape>> = BTreeMap::new();
+	let mut worklist: Vec<(usize, Vec<Shape>, Scope)> = vec![(entry, Vec::new(), Vec::new())];
+	while let Some((index, stack, scope)) = worklist.pop() {
+		if let Some(known) = stacks.get(&index) {
+			if known.len() != stack.len() {
+				problems.push(StackError::DepthMismatch(index, known.len(), stack.len()));
+			} else if *known != stack {
+				problems.push(StackError::ShapeMismatch(index));
+			}
+			continue;
+		}
+		stacks.insert(index, stack.clone());
+
+		let mut after = stack;
+		if let Err(problem) = step(&code[index], index, &scope, &mut after) {
+			problems.push(problem);
+			continue;
+		}
+		let mut scope = scope;
+		match code[index] {
+			Instruction::Begin => scope.push((index, Phase::Setup)),
+			Instruction::End => {
+				scope.pop();
+			}
+			Instruction::Call(_) => {
+				if let Some(block) = scope.last_mut() {
+					block.1 = Phase::Returned;
+				}
+			}
+			_ => {}
+		}
+
+		if let Instruction::Return = code[index] {
+			if !subroutine {
+				problems.push(StackError::ReturnOutsideSubroutine(index));
+			} else if !after.is_empty() {
+				problems.push(StackError::UnbalancedReturn(index, after.len()));
+			}
+		}
+		/* Running off the end of the program stops it like halt does */
+		if cfg.exits(index) && !after.is_empty() {
+			problems.push(StackError::LeftoverAtHalt(index, after.len()));
+		}
+
+		/* Jumps to undefined labels have no edge, the label checks report them */
+		for successor in cfg.next(index) {
+			worklist.push((successor, after.clone(), scope.clone()));
+		}
+	}
+
+	problems
+}
+
+/* Stack effect of one instruction, with the begin blocks open before it */
+pub fn step(instruction: &Instruction, index: usize, scope: &Scope, stack: &mut Vec<Shape>) -> Result<(), StackError> {
+	let pop_value = |stack: &mut Vec<Shape>| -> Result<(), StackError> {
+		match stack.pop() {
+			Some(Shape::Value) => Ok(()),
+			Some(Shape::Address(_)) => Err(StackError::ExpectedInteger(index)),
+			None => Err(StackError::Underflow(index)),
+		}
+	};
+	match *instruction {
+		Instruction::Push(_) | Instruction::Rvalue(_) => stack.push(Shape::Value),
+		Instruction::Lvalue(ref name) => stack.push(Shape::Address(Variable::new(resolve(scope, true), name))),
+		Instruction::Pop => {
+			stack.pop().ok_or(StackError::Underflow(index))?;
+		}
+		Instruction::Assign => {
+			pop_value(stack)?;
+			match stack.pop() {
+				Some(Shape::Address(_)) => {}
+				Some(Shape::Value) => return Err(StackError::ExpectedAddress(index)),
+				None => return Err(StackError::Underflow(index)),
+			}
+		}
+		Instruction::Copy => {
+			let top = stack.last().cloned().ok_or(StackError::Underflow(index))?;
+			stack.push(top);
+		}
+		Instruction::GoFalse(_) | Instruction::GoTrue(_) => pop_value(stack)?,
+		Instruction::Not | Instruction::Print => {
+			pop_value(stack)?;
+			stack.push(Shape::Value);
+		}
+		Instruction::Add | Instruction::Sub | Instruction::Mul | Instruction::Div | Instruction::Rem
+		| Instruction::And | Instruction::Or | Instruction::NotEqual | Instruction::LessEqual
+		| Instruction::GreaterEqual | Instruction::Less | Instruction::Greater | Instruction::Equal => {
+			pop_value(stack)?;
+			pop_value(stack)?;
+			stack.push(Shape::Value);
+		}
+		Instruction::Label(_) | Instruction::Goto(_) | Instruction::Halt | Instruction::Show(_)
+		| Instruction::Begin | Instruction::End | Instruction::Return | Instruction::Call(_) => {}
+	}
+	Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+	use super::{verify, StackError};
+
+	fn problems(source: &str) -> Vec<StackError> {
+		verify(&::parse(source).unwrap())
+	}
+
+	#[test]
+	fn accepts_correct_stack_use() {
+		assert_eq!(problems("lvalue x\npush 1\n:=\nrvalue x\nprint\npop\nhalt\n"), vec![]);
+		assert_eq!(problems(include_str!("../factProc.jaz")), vec![]);
+		/* Subroutines start from an empty stack of their own */
+		assert_eq!(problems("push 1\ncall f\npop\nhalt\nlabel f\npush 2\npop\nreturn\n"), vec![]);
+	}
+
+	#[test]
+	fn reports_underflow() {
+		assert_eq!(problems("pop\n"), vec![StackError::Underflow(0)]);
+		assert_eq!(problems("push 1\n+\n"), vec![StackError::Underflow(1)]);
+	}
+
+	#[test]
+	fn reports_values_and_addresses_mixed_up() {
+		assert_eq!(problems("push 1\npush 2\n:=\n"), vec![StackError::ExpectedAddress(2)]);
+		assert_eq!(problems("lvalue x\nprint\n"), vec![StackError::ExpectedInteger(1)]);
+	}
+
+	#[test]
+	fn reports_mismatches_at_joins() {
+		assert_eq!(problems("rvalue x\ngotrue a\npush 1\nlabel a\nhalt\n"), vec![StackError::DepthMismatch(3, 0, 1)]);
+		/* Same depth, but an lvalue on one path and a value on the other */
+		assert_eq!(problems("rvalue x\ngotrue a\nlvalue y\ngoto b\nlabel a\npush 1\nlabel b\npop\nhalt\n"), vec![StackError::ShapeMismatch(6)]);
+		/* Two lvalues naming different variables */
+		let source = "lvalue s\nrvalue x\ngotrue a\npop\nlvalue t\nlabel a\npush 42\n:=\n";
+		assert_eq!(problems(source), vec![StackError::ShapeMismatch(5)]);
+	}
+
+	#[test]
+	fn warns_about_values_left_at_halt() {
+		let found = problems("push 1\nprint\nhalt\n");
+		assert_eq!(found, vec![StackError::LeftoverAtHalt(2, 1)]);
+		assert!(!found[0].is_error());
+		assert_eq!(problems("push 1\npush 2\n"), vec![StackError::LeftoverAtHalt(1, 2)]);
+	}
+
+	#[test]
+	fn checks_returns() {
+		assert_eq!(problems("call f\nhalt\nlabel f\npush 1\nreturn\n"), vec![StackError::UnbalancedReturn(4, 1)]);
+		let found = problems("return\n");
+		assert_eq!(found, vec![StackError::ReturnOutsideSubroutine(0)]);
+		assert!(found[0].is_error());
+	}
+}
@@ -0,0 +1,154 @@
+/* Command Line Interface
+ * Turns the arguments into Options. `jaz file.jaz` and `jaz --run file.jaz`
+ * from before there were subcommands still mean transpile and run.
+ */
+
+use jaz::codegen::{Target, RUNTIME_ERROR_STATUS};
+
+/* Exit codes, so scripts can tell what went wrong */
+pub const EXIT_SUCCESS: i32 = 0;
+pub const EXIT_COMPILE: i32 = 1;		/* The jaz program was rejected */
+pub const EXIT_USAGE: i32 = 2;			/* The command line was wrong */
+pub const EXIT_RUNTIME: i32 = RUNTIME_ERROR_STATUS;	/* The program failed while running, generated code exits with it too */
+pub const EXIT_IO: i32 = 4;				/* Reading the input or writing the output failed */
+
+pub const USAGE: &str = "\
+Usage: jaz [COMMAND] [OPTIONS] FILE.jaz
+
+Commands:
+  transpile   Translate the program into another language (default)
+  run         Execute the program on the stack machine
+  check       Lex, parse and verify the program without generating code
+  tokens      Print the token stream
+  ast         Print the parsed program
+  cfg         Print the control flow graph in Graphviz DOT
+
+Options:
+  -o, --output FILE   Where to write the generated code, `-` for stdout
+                      (default: FILE with the target's extension)
+  -t, --target LANG   Language to generate: cpp (default), c, rust, wat,
+                      x86-64 or llvm. LLVM IR needs LLVM 15 or later, or
+                      LLVM 14's opt and llc with -opaque-pointers
+  -O0, -O1            Leave the program as written (default), or optimize it
+                      before running or generating code
+  -v, --verbose       Report what each stage does
+  -q, --quiet         Only report errors
+      --color WHEN    Colour diagnostics: auto (default), always or never
+  -h, --help          Print this message
+
+Exit codes: 0 success, 1 compile error, 2 usage error, 3 runtime error, 4 I/O error";
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Command {
+	Transpile,
+	Run,
+	Check,
+	Tokens,
+	Ast,
+	Cfg,
+	Help,
+}
+
+impl Command {
+	fn parse(name: &str) -> Option<Command> {
+		match name {
+			"transpile" => Some(Command::Transpile),
+			"run" => Some(Command::Run),
+			"check" => Some(Command::Check),
+			"tokens" => Some(Command::Tokens),
+			"ast" => Some(Command::Ast),
+			"cfg" => Some(Command::Cfg),
+			"help" => Some(Command::Help),
+			_ => None,
+		}
+	}
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Color {
+	Auto,
+	Always,
+	Never,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord)]
+pub enum OptLevel {
+	O0,		/* Instructions as written */
+	O1,		/* Folded and simplified, see jaz::optimizer */
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord)]
+pub enum Verbosity {
+	Quiet,		/* Errors only */
+	Normal,		/* Errors, warnings and what was written where */
+	Verbose,	/* Also what every stage did */
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Options {
+	pub command: Command,
+	pub input: String,
+	pub output: Option<String>,
+	pub target: Target,
+	pub optimization: OptLevel,
+	pub verbosity: Verbosity,
+	pub color: Color,
+}
+
+/* Parses the arguments after the program name */
+pub fn parse(args: &[String]) -> Result<Options, String> {
+	let mut args = args.iter().peekable();
+	let mut command = match args.peek().and_then(|arg| Command::parse(arg)) {
+		Some(command) => {
+			args.next();
+			command
+		}
+		None => Command::Transpile,
+	};
+
+	let mut input: Option<String> = None;
+	let mut output: Option<String> = None;
+	let mut target = Target::Cpp;
+	let mut optimization = OptLevel::O0;
+	let mut verbosity = Verbosity::Normal;
+	let mut color = Color::Auto;
+
+	while let Some(arg) = args.next() {
+		let mut value = |flag: &str| args.next().cloned().ok_or_else(|| format!("`{}` needs a value", flag));
+		match arg.as_str() {
+			"--run" => command = Command::Run,
+			"-o" | "--output" => output = Some(value(arg)?),
+			"-t" | "--target" => {
+				let name = value(arg)?;
+				target = Target::from_name(&name).ok_or_else(|| format!("unknown target `{}`", name))?;
+			}
+			"-O0" => optimization = OptLevel::O0,
+			"-O1" => optimization = OptLevel::O1,
+			"-v" | "--verbose" => verbosity = Verbosity::Verbose,
+			"-q" | "--quiet" => verbosity = Verbosity::Quiet,
+			"--color" => {
+				color = match value(arg)?.as_str() {
+					"auto" => Color::Auto,
+					"always" => Color::Always,
+					"never" => Color::Never,
+					other => return Err(format!("`--color` takes auto, always or never, not `{}`", other)),
+				}
+			}
+			"-h" | "--help" => command = Command::Help,
+			flag if flag.starts_with('-') && flag != "-" => return Err(format!("unknown option `{}`", flag)),
+			_ if input.is_none() => input = Some(arg.clone()),
+			_ => return Err(format!("unexpected argument `{}`", arg)),
+		}
+	}
+
+	if command == Command::Help {
+		return Ok(Options { command, input: String::new(), output, target, optimization, verbosity, color });
+	}
+	if output.is_some() && command != Command::Transpile {
+		return Err("`--output` only applies to transpile".to_string());
+	}
+	match input {
+		Some(input) => Ok(Options { command, input, output, target, optimization, verbosity, color }),
+		None => Err("no input file".to_string()),
+	}
+}
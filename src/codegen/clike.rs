@@ -0,0 +1,371 @@
+/* C and C++ Backends
+ * The two languages only differ in their keywords, how they print and a few
+ * spellings, so one generator writes both and asks the Dialect about those.
+ * Either way the output is a single self-contained source file: int64_t
+ * variables, subroutines as void functions taking pointers for the variables
+ * they hand back, and loops and conditionals where structuring found them.
+ */
+use codegen::structure::{structure, Structured};
+use codegen::{lower, Argument, BinaryOp, CodegenError, Expr, Frame, Function, Mode, Module, Names, Statement, Variable};
+use codegen::{DIVISION_BY_ZERO, RUNTIME_ERROR_STATUS};
+use parsetree::Program;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Dialect {
+	C,		/* C99 with stdio */
+	Cpp,	/* C++ with iostream */
+}
+
+impl Dialect {
+	fn includes(self) -> &'static str {
+		match self {
+			Dialect::C => "#include <inttypes.h>\n#include <stdint.h>\n#include <stdio.h>\n#include <stdlib.h>\n",
+			Dialect::Cpp => "#include <iostream>\n#include <stdint.h>\n#include <stdlib.h>\n",
+		}
+	}
+
+	/* A statement printing a value, or a string literal, on a line of its own */
+	fn print(self, value: &str) -> String {
+		match self {
+			Dialect::C => format!("printf(\"%\" PRId64 \"\\n\", {});", value),
+			Dialect::Cpp => format!("std::cout << {} << std::endl;", value),
+		}
+	}
+
+	fn show(self, literal: &str) -> String {
+		match self {
+			Dialect::C => format!("puts({});", literal),
+			Dialect::Cpp => format!("std::cout << {} << std::endl;", literal),
+		}
+	}
+
+	/* Statements reporting a runtime error, after what was printed so far */
+	fn fail(self, message: &str) -> String {
+		match self {
+			Dialect::C => format!("fflush(stdout);\n\t\tfputs({}, stderr);", string_literal(&format!("{}\n", message))),
+			Dialect::Cpp => format!("std::cout.flush();\n\t\tstd::cerr << {} << std::endl;", string_literal(message)),
+		}
+	}
+
+	fn forever(self) -> &'static str {
+		match self {
+			Dialect::C => "while (1)",
+			Dialect::Cpp => "while (true)",
+		}
+	}
+}
+
+pub fn generate(program: &Program, dialect: Dialect) -> Result<String, CodegenError> {
+	let module = lower(program)?;
+	let names = Names::new(&module, &[]);
+	let mut out = String::new();
+
+	out.push_str(dialect.includes());
+	write_arithmetic(&mut out, &module, dialect);
+
+	/* Every variable of the program entry's own frame starts at zero */
+	if !module.variables.is_empty() {
+		out.push('\n');
+		for variable in &module.variables {
+			out.push_str(&format!("int64_t {} = 0;\n", variable_identifier(&names, &module.functions[0], &Variable::new(Frame::Own, variable))));
+		}
+	}
+
+	let subroutines: Vec<&Function> = module.functions.iter().filter(|f| f.label.is_some()).collect();
+	if !subroutines.is_empty() {
+		out.push('\n');
+		for function in &subroutines {
+			out.push_str(&format!("{};\n", signature(function, &names, dialect)));
+		}
+	}
+
+	for function in &module.functions {
+		out.push('\n');
+		write_function(&mut out, function, &names, dialect);
+	}
+
+	Ok(out)
+}
+
+/* Helpers for the arithmetic the program does, see codegen::DIVISION_BY_ZERO. Signed
+ * overflow is undefined in C and C++, unsigned arithmetic wraps, and converting back
+ * is two's complement on every compiler that has int64_t.
+ */
+fn write_arithmetic(out: &mut String, module: &Module, dialect: Dialect) {
+	for &(op, name, symbol) in &[(BinaryOp::Add, "jaz_add", "+"), (BinaryOp::Sub, "jaz_sub", "-"), (BinaryOp::Mul, "jaz_mul", "*")] {
+		if module.applies(op) {
+			out.push_str(&format!(
+				"\nstatic int64_t {}(int64_t a, int64_t b) {{\n\treturn (int64_t)((uint64_t)a {} (uint64_t)b);\n}}\n",
+				name, symbol
+			));
+		}
+	}
+	/* Dividing the most negative value by -1 overflows, and traps on x86 */
+	for &(op, name, minus_one) in &[(BinaryOp::Div, "jaz_div", "(int64_t)(0 - (uint64_t)a)"), (BinaryOp::Rem, "jaz_rem", "0")] {
+		if module.applies(op) {
+			out.push_str(&format!("\nstatic int64_t {}(int64_t a, int64_t b) {{\n", name));
+			out.push_str(&format!("\tif (b == 0) {{\n\t\t{}\n\t\texit({});\n\t}}\n", dialect.fail(DIVISION_BY_ZERO), RUNTIME_ERROR_STATUS));
+			out.push_str(&format!("\treturn b == -1 ? {} : a {} b;\n}}\n", minus_one, operator(op)));
+		}
+	}
+}
+
+/* Outputs are pointers to the caller's variables, inputs are copies */
+fn signature(function: &Function, names: &Names, dialect: Dialect) -> String {
+	match function.label {
+		Some(ref label) => {
+			let parameters: Vec<String> = function.parameters.iter()
+				.filter(|parameter| parameter.mode != Mode::Local)
+				.map(|parameter| {
+					let identifier = variable_identifier(names, function, &Variable::new(Frame::Own, &parameter.name));
+					if parameter.mode.by_pointer() {
+						format!("int64_t* {}", identifier)
+					} else {
+						format!("int64_t {}", identifier)
+					}
+				})
+				.collect();
+			/* An empty list leaves the parameters unspecified in C */
+			let parameters = if parameters.is_empty() && dialect == Dialect::C { "void".to_string() } else { parameters.join(", ") };
+			format!("void {}({})", function_identifier(names, label), parameters)
+		}
+		None if dialect == Dialect::C => "int main(void)".to_string(),
+		None => "int main()".to_string(),
+	}
+}
+
+fn write_function(out: &mut String, function: &Function, names: &Names, dialect: Dialect) {
+	let context = Context { function, names, dialect };
+	out.push_str(&format!("{} {{\n", signature(function, names, dialect)));
+
+	if function.slots > 0 {
+		let slots: Vec<String> = (0..function.slots).map(|slot| format!("{} = 0", names.slot(slot))).collect();
+		out.push_str(&format!("\tint64_t {};\n", slots.join(", ")));
+	}
+	let locals: Vec<Variable> = function.parameters.iter()
+		.filter(|parameter| parameter.mode == Mode::Local)
+		.map(|parameter| Variable::new(Frame::Own, &parameter.name))
+		.chain(function.locals.iter().cloned())
+		.collect();
+	if !locals.is_empty() {
+		let locals: Vec<String> = locals.iter().map(|local| format!("{} = 0", variable_identifier(names, function, local))).collect();
+		out.push_str(&format!("\tint64_t {};\n", locals.join(", ")));
+	}
+
+	context.block(out, &structure(&function.body), 1);
+	out.push_str("}\n");
+}
+
+/* The function whose body is being written, variables are spelled differently in each */
+struct Context<'a> {
+	function: &'a Function,
+	names: &'a Names,
+	dialect: Dialect,
+}
+
+impl<'a> Context<'a> {
+	/* Statements nested `depth` levels deep, labels stay in the first column */
+	fn block(&self, out: &mut String, items: &[Structured], depth: usize) {
+		let indent = "\t".repeat(depth);
+		for (number, item) in items.iter().enumerate() {
+			match *item {
+				Structured::Simple(Statement::Label(ref label)) => {
+					/* A label has to label a statement, even at the end of a block */
+					let empty = if number + 1 == items.len() { ";" } else { "" };
+					out.push_str(&format!("{}:{}\n", label_identifier(self.names, label), empty));
+				}
+				Structured::Simple(ref statement) => out.push_str(&format!("{}{}\n", indent, self.statement(statement))),
+				Structured::If(ref condition, ref then, ref otherwise) => {
+					let test = self.expression(condition);
+					match (then.as_slice(), otherwise.is_empty()) {
+						([ref jump], true) if is_jump(jump) => {
+							out.push_str(&format!("{}if ({}) {}\n", indent, test, self.jump(jump)));
+						}
+						_ => {
+							out.push_str(&format!("{}if ({}) {{\n", indent, test));
+							self.block(out, then, depth + 1);
+							if !otherwise.is_empty() {
+								out.push_str(&format!("{}}} else {{\n", indent));
+								self.block(out, otherwise, depth + 1);
+							}
+							out.push_str(&format!("{}}}\n", indent));
+						}
+					}
+				}
+				Structured::While(ref condition, ref body) => {
+					out.push_str(&format!("{}while ({}) {{\n", indent, self.expression(condition)));
+					self.block(out, body, depth + 1);
+					out.push_str(&format!("{}}}\n", indent));
+				}
+				Structured::Loop(ref body) => {
+					out.push_str(&format!("{}{} {{\n", indent, self.dialect.forever()));
+					self.block(out, body, depth + 1);
+					out.push_str(&format!("{}}}\n", indent));
+				}
+				Structured::Break | Structured::Continue => out.push_str(&format!("{}{}\n", indent, self.jump(item))),
+			}
+		}
+	}
+
+	fn jump(&self, item: &Structured) -> String {
+		match *item {
+			Structured::Break => "break;".to_string(),
+			Structured::Continue => "continue;".to_string(),
+			Structured::Simple(ref statement) => self.statement(statement),
+			_ => unreachable!(),
+		}
+	}
+
+	fn statement(&self, statement: &Statement) -> String {
+		match *statement {
+			Statement::Assign(ref variable, ref value) => format!("{} = {};", self.variable(variable), self.expression(value)),
+			Statement::Spill(slot, ref value) => format!("{} = {};", self.names.slot(slot), self.expression(value)),
+			Statement::Evaluate(ref value) => format!("(void)({});", self.expression(value)),
+			Statement::Print(ref value) => self.dialect.print(&self.integer(value)),
+			Statement::Show(ref text) => self.dialect.show(&string_literal(text)),
+			Statement::Call(ref label, ref arguments) => {
+				let arguments: Vec<String> = arguments.iter().map(|argument| match *argument {
+					Argument::Value(ref variable) => self.variable(variable),
+					Argument::Reference(ref variable) => self.address(variable),
+				}).collect();
+				format!("{}({});", function_identifier(self.names, label), arguments.join(", "))
+			}
+			Statement::Label(ref label) => format!("{}:", label_identifier(self.names, label)),
+			Statement::Goto(ref label) => format!("goto {};", label_identifier(self.names, label)),
+			Statement::Branch(ref condition, jump_if, ref label) => {
+				let test = if jump_if { self.operand(condition) } else { format!("!{}", self.operand(condition)) };
+				format!("if ({}) goto {};", test, label_identifier(self.names, label))
+			}
+			Statement::Return => "return;".to_string(),
+			Statement::Halt if self.function.label.is_none() => "return 0;".to_string(),
+			Statement::Halt => "exit(0);".to_string(),
+		}
+	}
+
+	/* Pointers are dereferenced in parentheses, which also keeps `/ *x` from opening a comment */
+	fn variable(&self, variable: &Variable) -> String {
+		let identifier = variable_identifier(self.names, self.function, variable);
+		if self.function.is_pointer(variable) {
+			format!("(*{})", identifier)
+		} else {
+			identifier
+		}
+	}
+
+	/* Pointer parameters already are addresses, they are passed on as they are */
+	fn address(&self, variable: &Variable) -> String {
+		let identifier = variable_identifier(self.names, self.function, variable);
+		if self.function.is_pointer(variable) {
+			identifier
+		} else {
+			format!("&{}", identifier)
+		}
+	}
+
+	/* An expression that can stand on its own, without outer parentheses */
+	fn expression(&self, value: &Expr) -> String {
+		match *value {
+			Expr::Binary(op, ref left, ref right) => match helper(op) {
+				Some(helper) => format!("{}({}, {})", helper, self.expression(left), self.expression(right)),
+				/* && and || would skip a division by zero on the right */
+				None if (op == BinaryOp::And || op == BinaryOp::Or) && right.divides() => format!(
+					"({} != 0) {} ({} != 0)",
+					self.operand(left), if op == BinaryOp::And { "&" } else { "|" }, self.operand(right)
+				),
+				None => format!("{} {} {}", self.operand(left), operator(op), self.operand(right)),
+			},
+			Expr::Not(ref operand) => format!("!{}", self.operand(operand)),
+			_ => self.operand(value),
+		}
+	}
+
+	/* An operand that is an int64_t, where C would otherwise see an int, as printf has to */
+	fn integer(&self, value: &Expr) -> String {
+		match *value {
+			Expr::Variable(_) | Expr::Slot(_) => self.operand(value),
+			Expr::Binary(op, _, _) if helper(op).is_some() => self.operand(value),
+			_ if self.dialect == Dialect::C => format!("(int64_t){}", self.operand(value)),
+			_ => self.operand(value),
+		}
+	}
+
+	/* An expression that binds tighter than any operator around it */
+	fn operand(&self, value: &Expr) -> String {
+		match *value {
+			Expr::Constant(i64::MIN) => "INT64_MIN".to_string(),
+			Expr::Constant(constant) if constant < 0 => format!("({})", constant),
+			Expr::Constant(constant) => constant.to_string(),
+			Expr::Variable(ref variable) => self.variable(variable),
+			Expr::Slot(slot) => self.names.slot(slot),
+			Expr::Binary(op, _, _) if helper(op).is_some() => self.expression(value),
+			Expr::Binary(..) | Expr::Not(_) => format!("({})", self.expression(value)),
+		}
+	}
+}
+
+/* Every name from the program gets a prefix, so none can be a keyword or
+ * anything the headers declare, like printf, abs or div
+ */
+fn variable_identifier(names: &Names, function: &Function, variable: &Variable) -> String {
+	let prefix = if function.label.is_none() && variable.frame == Frame::Own { "g_" } else { "v_" };
+	format!("{}{}", prefix, names.variable(function, variable))
+}
+
+fn function_identifier(names: &Names, label: &str) -> String {
+	format!("f_{}", names.function(label))
+}
+
+fn label_identifier(names: &Names, label: &str) -> String {
+	format!("l_{}", names.label(label))
+}
+
+/* Statements an if can run without braces */
+fn is_jump(item: &Structured) -> bool {
+	matches!(*item, Structured::Break | Structured::Continue | Structured::Simple(Statement::Goto(_)))
+}
+
+/* Arithmetic goes through the helpers write_arithmetic defines */
+fn helper(op: BinaryOp) -> Option<&'static str> {
+	match op {
+		BinaryOp::Add => Some("jaz_add"),
+		BinaryOp::Sub => Some("jaz_sub"),
+		BinaryOp::Mul => Some("jaz_mul"),
+		BinaryOp::Div => Some("jaz_div"),
+		BinaryOp::Rem => Some("jaz_rem"),
+		_ => None,
+	}
+}
+
+fn operator(op: BinaryOp) -> &'static str {
+	match op {
+		BinaryOp::Add => "+",
+		BinaryOp::Sub => "-",
+		BinaryOp::Mul => "*",
+		BinaryOp::Div => "/",
+		BinaryOp::Rem => "%",
+		BinaryOp::And => "&&",
+		BinaryOp::Or => "||",
+		BinaryOp::NotEqual => "!=",
+		BinaryOp::LessEqual => "<=",
+		BinaryOp::GreaterEqual => ">=",
+		BinaryOp::Less => "<",
+		BinaryOp::Greater => ">",
+		BinaryOp::Equal => "==",
+	}
+}
+
+fn string_literal(text: &str) -> String {
+	let mut literal = String::from("\"");
+	for c in text.chars() {
+		match c {
+			'"' => literal.push_str("\\\""),
+			'\\' => literal.push_str("\\\\"),
+			'\t' => literal.push_str("\\t"),
+			'\n' => literal.push_str("\\n"),
+			'?' => literal.push_str("\\?"),	/* Avoids trigraphs */
+			_ => literal.push(c),
+		}
+	}
+	literal.push('"');
+	literal
+}
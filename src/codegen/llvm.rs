@@ -0,0 +1,365 @@
+/* LLVM Backend
+ * Writes a module of textual LLVM IR with opaque pointers (LLVM 15 and later,
+ * or 14 with -opaque-pointers), to be built with `clang program.ll` or llc and
+ * cc. The program entry's own frame becomes internal globals, every other
+ * variable and stack slot an alloca in the entry block of its function, which
+ * LLVM's mem2reg turns into registers. Subroutines are functions taking an i64
+ * for each variable they only read and a pointer to each one they hand back.
+ * Labels start basic blocks, and a block that falls through to the next one
+ * ends in an explicit branch, as LLVM wants every block terminated.
+ */
+use codegen::{lower, Argument, BinaryOp, CodegenError, Expr, Frame, Function, Mode, Module, Names, Parameter, Statement, Variable};
+use codegen::{DIVISION_BY_ZERO, RUNTIME_ERROR_STATUS};
+use parsetree::Program;
+
+pub fn generate(program: &Program) -> Result<String, CodegenError> {
+	let module = lower(program)?;
+	/* Names are prefixed by what they are, so they cannot clash with each other or the C library */
+	let names = Names::new(&module, &[]);
+	let statements = || module.functions.iter().flat_map(|function| &function.body);
+	let printing = statements().any(|statement| matches!(*statement, Statement::Print(_)));
+	let dividing = module.applies(BinaryOp::Div) || module.applies(BinaryOp::Rem);
+
+	let mut texts: Vec<&str> = Vec::new();
+	for statement in statements() {
+		if let Statement::Show(ref text) = *statement {
+			if !texts.contains(&text.as_str()) {
+				texts.push(text);
+			}
+		}
+	}
+
+	let mut out = String::new();
+	let main = &module.functions[0];
+	for variable in &module.variables {
+		out.push_str(&format!("@{} = internal global i64 0\n", symbol("g_", names.variable(main, &Variable::new(Frame::Own, variable)))));
+	}
+	for (number, text) in texts.iter().enumerate() {
+		out.push_str(&format!("@.show.{} = private unnamed_addr constant {}\n", number, string_constant(text)));
+	}
+	if printing {
+		out.push_str(&format!("@.print_format = private unnamed_addr constant {}\n", string_constant("%ld\n")));
+	}
+	if dividing {
+		out.push_str(&format!("@.division_by_zero = private unnamed_addr constant {}\n", string_constant(&format!("{}\n", DIVISION_BY_ZERO))));
+	}
+
+	for function in &module.functions {
+		let mut context = Context { function, names: &names, texts: &texts, temporaries: 0, blocks: 0 };
+		context.function(&mut out);
+	}
+	if dividing {
+		write_division(&mut out, &module);
+	}
+
+	out.push('\n');
+	if printing {
+		out.push_str("declare i32 @printf(ptr, ...)\n");
+	}
+	if !texts.is_empty() {
+		out.push_str("declare i32 @puts(ptr)\n");
+	}
+	if dividing {
+		out.push_str("@stderr = external global ptr\ndeclare i32 @fflush(ptr)\ndeclare i32 @fputs(ptr, ptr)\n");
+	}
+	if dividing || module.functions.iter().any(|function| function.label.is_some() && function.body.contains(&Statement::Halt)) {
+		out.push_str("declare void @exit(i32) noreturn\n");
+	}
+	Ok(out)
+}
+
+/* Division helpers, see codegen::DIVISION_BY_ZERO. sdiv is undefined where the
+ * interpreter wraps, so the most negative value divided by -1 is handled first.
+ */
+fn write_division(out: &mut String, module: &Module) {
+	for &(op, name, instruction, by_minus_one) in &[
+		(BinaryOp::Div, "jaz_div", "sdiv", "sub i64 0, %a"),
+		(BinaryOp::Rem, "jaz_rem", "srem", "add i64 0, 0"),
+	] {
+		if module.applies(op) {
+			out.push_str(&format!("\ndefine internal i64 @{}(i64 %a, i64 %b) {{\nentry:\n", name));
+			out.push_str("\t%zero = icmp eq i64 %b, 0\n\tbr i1 %zero, label %fail, label %nonzero\n");
+			/* Output printed so far goes out before the message, fflush(NULL) flushes every stream */
+			out.push_str("fail:\n\tcall i32 @fflush(ptr null)\n\t%stderr = load ptr, ptr @stderr\n");
+			out.push_str("\tcall i32 @fputs(ptr @.division_by_zero, ptr %stderr)\n");
+			out.push_str(&format!("\tcall void @exit(i32 {})\n\tunreachable\n", RUNTIME_ERROR_STATUS));
+			out.push_str("nonzero:\n\t%minus_one = icmp eq i64 %b, -1\n\tbr i1 %minus_one, label %wrap, label %divide\n");
+			out.push_str(&format!("wrap:\n\t%wrapped = {}\n\tret i64 %wrapped\n", by_minus_one));
+			out.push_str(&format!("divide:\n\t%result = {} i64 %a, %b\n\tret i64 %result\n}}\n", instruction));
+		}
+	}
+}
+
+/* The function being written, and the names used up in it so far */
+struct Context<'a> {
+	function: &'a Function,
+	names: &'a Names,
+	texts: &'a [&'a str],
+	temporaries: usize,
+	blocks: usize,		/* Blocks started after a conditional branch or in unlabelled code */
+}
+
+impl<'a> Context<'a> {
+	fn function(&mut self, out: &mut String) {
+		let function = self.function;
+		let own = |parameter: &Parameter| Variable::new(Frame::Own, &parameter.name);
+
+		let mut parameters: Vec<String> = Vec::new();
+		let mut allocas: Vec<Variable> = Vec::new();
+		for parameter in &function.parameters {
+			let name = symbol("", self.names.variable(function, &own(parameter)));
+			match parameter.mode {
+				Mode::In => {
+					parameters.push(format!("i64 %a_{}", name));
+					allocas.push(own(parameter));
+				}
+				Mode::Out | Mode::InOut => parameters.push(format!("ptr %p_{}", name)),
+				Mode::Local => allocas.push(own(parameter)),
+			}
+		}
+		allocas.extend(function.locals.iter().cloned());
+
+		match function.label {
+			Some(ref label) => out.push_str(&format!("\ndefine internal void @{}({}) {{\n", symbol("f_", self.names.function(label)), parameters.join(", "))),
+			None => out.push_str("\ndefine i32 @main() {\n"),
+		}
+		out.push_str("entry:\n");
+		for variable in &allocas {
+			let name = self.local(variable);
+			out.push_str(&format!("\t{} = alloca i64\n\tstore i64 0, ptr {}\n", name, name));
+		}
+		for slot in 0..function.slots {
+			out.push_str(&format!("\t%s{} = alloca i64\n", slot));
+		}
+		for parameter in function.parameters.iter().filter(|parameter| parameter.mode == Mode::In) {
+			let name = symbol("", self.names.variable(function, &own(parameter)));
+			out.push_str(&format!("\tstore i64 %a_{}, ptr %v_{}\n", name, name));
+		}
+
+		/* Whether the current block has ended, so the next statement needs a block of its own */
+		let mut terminated = false;
+		for statement in &function.body {
+			if let Statement::Label(ref label) = *statement {
+				let block = symbol("L_", label);
+				if !terminated {
+					out.push_str(&format!("\tbr label %{}\n", block));
+				}
+				out.push_str(&format!("{}:\n", block));
+				terminated = false;
+				continue;
+			}
+			if terminated {
+				self.blocks += 1;
+				out.push_str(&format!("next{}:\n", self.blocks));
+			}
+			terminated = self.statement(out, statement);
+		}
+		out.push_str("}\n");
+	}
+
+	/* Writes one statement, returns whether it ended the block */
+	fn statement(&mut self, out: &mut String, statement: &Statement) -> bool {
+		match *statement {
+			Statement::Assign(ref variable, ref value) => {
+				let value = self.value(out, value);
+				let pointer = self.pointer(variable);
+				out.push_str(&format!("\tstore i64 {}, ptr {}\n", value, pointer));
+			}
+			Statement::Spill(slot, ref value) => {
+				let value = self.value(out, value);
+				out.push_str(&format!("\tstore i64 {}, ptr %s{}\n", value, slot));
+			}
+			Statement::Evaluate(ref value) => {
+				self.value(out, value);
+			}
+			Statement::Print(ref value) => {
+				let value = self.value(out, value);
+				out.push_str(&format!("\tcall i32 (ptr, ...) @printf(ptr @.print_format, i64 {})\n", value));
+			}
+			Statement::Show(ref text) => {
+				let number = self.texts.iter().position(|shown| shown == text).unwrap();
+				out.push_str(&format!("\tcall i32 @puts(ptr @.show.{})\n", number));
+			}
+			Statement::Call(ref label, ref arguments) => {
+				let mut operands: Vec<String> = Vec::new();
+				for argument in arguments {
+					operands.push(match *argument {
+						Argument::Value(ref variable) => format!("i64 {}", self.load(out, variable)),
+						Argument::Reference(ref variable) => format!("ptr {}", self.pointer(variable)),
+					});
+				}
+				out.push_str(&format!("\tcall void @{}({})\n", symbol("f_", self.names.function(label)), operands.join(", ")));
+			}
+			Statement::Goto(ref label) => {
+				out.push_str(&format!("\tbr label %{}\n", symbol("L_", label)));
+				return true;
+			}
+			Statement::Branch(ref condition, jump_if, ref label) => {
+				let condition = self.condition(out, condition);
+				self.blocks += 1;
+				let (taken, next) = (format!("%{}", symbol("L_", label)), format!("%next{}", self.blocks));
+				let (then, otherwise) = if jump_if { (taken, next) } else { (next, taken) };
+				out.push_str(&format!("\tbr i1 {}, label {}, label {}\n", condition, then, otherwise));
+				out.push_str(&format!("next{}:\n", self.blocks));
+			}
+			Statement::Return => {
+				out.push_str("\tret void\n");
+				return true;
+			}
+			Statement::Halt if self.function.label.is_none() => {
+				out.push_str("\tret i32 0\n");
+				return true;
+			}
+			Statement::Halt => {
+				out.push_str("\tcall void @exit(i32 0)\n\tunreachable\n");
+				return true;
+			}
+			Statement::Label(_) => unreachable!(),
+		}
+		false
+	}
+
+	fn temporary(&mut self) -> String {
+		self.temporaries += 1;
+		format!("%t{}", self.temporaries)
+	}
+
+	/* The alloca of a variable that is not global and not handed in by pointer */
+	fn local(&self, variable: &Variable) -> String {
+		format!("%v_{}", symbol("", self.names.variable(self.function, variable)))
+	}
+
+	/* Where a variable lives: a global, a pointer the caller passed, or an alloca */
+	fn pointer(&self, variable: &Variable) -> String {
+		if self.function.label.is_none() && variable.frame == Frame::Own {
+			format!("@{}", symbol("g_", self.names.variable(self.function, variable)))
+		} else if self.function.is_pointer(variable) {
+			format!("%p_{}", symbol("", self.names.variable(self.function, variable)))
+		} else {
+			self.local(variable)
+		}
+	}
+
+	fn load(&mut self, out: &mut String, variable: &Variable) -> String {
+		let pointer = self.pointer(variable);
+		let temporary = self.temporary();
+		out.push_str(&format!("\t{} = load i64, ptr {}\n", temporary, pointer));
+		temporary
+	}
+
+	/* An i64 operand */
+	fn value(&mut self, out: &mut String, value: &Expr) -> String {
+		match *value {
+			Expr::Constant(constant) => constant.to_string(),
+			Expr::Variable(ref variable) => self.load(out, variable),
+			Expr::Slot(slot) => {
+				let temporary = self.temporary();
+				out.push_str(&format!("\t{} = load i64, ptr %s{}\n", temporary, slot));
+				temporary
+			}
+			Expr::Binary(op, ref left, ref right) if arithmetic(op).is_some() => {
+				let (left, right) = (self.value(out, left), self.value(out, right));
+				let temporary = self.temporary();
+				match arithmetic(op).unwrap() {
+					instruction if instruction.starts_with('@') => {
+						out.push_str(&format!("\t{} = call i64 {}(i64 {}, i64 {})\n", temporary, instruction, left, right));
+					}
+					instruction => out.push_str(&format!("\t{} = {} i64 {}, {}\n", temporary, instruction, left, right)),
+				}
+				temporary
+			}
+			Expr::Binary(..) | Expr::Not(_) => {
+				let condition = self.condition(out, value);
+				let temporary = self.temporary();
+				out.push_str(&format!("\t{} = zext i1 {} to i64\n", temporary, condition));
+				temporary
+			}
+		}
+	}
+
+	/* An i1 operand. Both sides of And and Or are evaluated, as the stack machine does */
+	fn condition(&mut self, out: &mut String, value: &Expr) -> String {
+		let temporary;
+		match *value {
+			Expr::Binary(op, ref left, ref right) if logic(op).is_some() => {
+				let (left, right) = (self.condition(out, left), self.condition(out, right));
+				temporary = self.temporary();
+				out.push_str(&format!("\t{} = {} i1 {}, {}\n", temporary, logic(op).unwrap(), left, right));
+			}
+			Expr::Binary(op, ref left, ref right) if comparison(op).is_some() => {
+				let (left, right) = (self.value(out, left), self.value(out, right));
+				temporary = self.temporary();
+				out.push_str(&format!("\t{} = icmp {} i64 {}, {}\n", temporary, comparison(op).unwrap(), left, right));
+			}
+			Expr::Not(ref operand) => {
+				let operand = self.condition(out, operand);
+				temporary = self.temporary();
+				out.push_str(&format!("\t{} = xor i1 {}, true\n", temporary, operand));
+			}
+			_ => {
+				let value = self.value(out, value);
+				temporary = self.temporary();
+				out.push_str(&format!("\t{} = icmp ne i64 {}, 0\n", temporary, value));
+			}
+		}
+		temporary
+	}
+}
+
+/* Addition, subtraction and multiplication without nsw wrap around like the interpreter's */
+fn arithmetic(op: BinaryOp) -> Option<&'static str> {
+	match op {
+		BinaryOp::Add => Some("add"),
+		BinaryOp::Sub => Some("sub"),
+		BinaryOp::Mul => Some("mul"),
+		BinaryOp::Div => Some("@jaz_div"),
+		BinaryOp::Rem => Some("@jaz_rem"),
+		_ => None,
+	}
+}
+
+fn logic(op: BinaryOp) -> Option<&'static str> {
+	match op {
+		BinaryOp::And => Some("and"),
+		BinaryOp::Or => Some("or"),
+		_ => None,
+	}
+}
+
+fn comparison(op: BinaryOp) -> Option<&'static str> {
+	match op {
+		BinaryOp::NotEqual => Some("ne"),
+		BinaryOp::LessEqual => Some("sle"),
+		BinaryOp::GreaterEqual => Some("sge"),
+		BinaryOp::Less => Some("slt"),
+		BinaryOp::Greater => Some("sgt"),
+		BinaryOp::Equal => Some("eq"),
+		_ => None,
+	}
+}
+
+/* jaz names may hold any letter, LLVM names only ASCII. `.` never occurs in a jaz name */
+fn symbol(prefix: &str, name: &str) -> String {
+	let mut symbol = prefix.to_string();
+	for c in name.chars() {
+		if c.is_ascii_alphanumeric() || c == '_' {
+			symbol.push(c);
+		} else {
+			symbol.push_str(&format!(".{:x}.", c as u32));
+		}
+	}
+	symbol
+}
+
+/* A NUL-terminated byte array, as C functions take text */
+fn string_constant(text: &str) -> String {
+	let mut literal = String::new();
+	for &byte in text.as_bytes() {
+		if byte.is_ascii_graphic() && byte != b'"' && byte != b'\\' || byte == b' ' {
+			literal.push(byte as char);
+		} else {
+			literal.push_str(&format!("\\{:02X}", byte));
+		}
+	}
+	format!("[{} x i8] c\"{}\\00\"", text.len() + 1, literal)
+}
@@ -0,0 +1,1028 @@
+/* Code Generation
+ * Lowers a parsed program into functions made of simple statements. The jaz
+ * value stack is simulated at compile time: values become expressions, and are
+ * only spilled into numbered stack slots where control flow joins or where
+ * evaluation order matters. Addresses pushed by lvalue are tracked by name.
+ *
+ * Variables belong to frames, following the scoping rules the interpreter
+ * implements. The program entry's own frame becomes global variables, and
+ * every begin block's frame becomes local variables of the function the block
+ * is in, so recursion gets fresh ones. A subroutine's own frame is whichever
+ * frame its caller runs the call in, so it becomes the subroutine's parameters,
+ * bound to the caller's variables separately at every call site. Liveness
+ * decides how each one is passed, see Mode. Instructions no run of the
+ * program reaches are not generated, analysis::reachability warns about them.
+ */
+pub mod clike;
+pub mod llvm;
+pub mod rust;
+pub mod structure;
+pub mod wat;
+pub mod x86_64;
+
+pub use analysis::scope::{Frame, Variable};
+
+use std::collections::{BTreeMap, BTreeSet, HashMap, HashSet};
+use std::fmt;
+
+use analysis::cfg::Cfg;
+use analysis::labels::LabelKind;
+use analysis::scope::{call_frame, resolve, Phase, Scope};
+use analysis::stack::{step, Shape, StackError};
+use codegen::clike::Dialect;
+use diagnostics::Diagnostic;
+use parsetree::{Instruction, Program};
+
+/* Languages a program can be generated in */
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Target {
+	C,
+	Cpp,
+	Rust,
+	Wat,
+	X86_64,
+	Llvm,
+}
+
+impl Target {
+	pub fn from_name(name: &str) -> Option<Target> {
+		match name {
+			"c" => Some(Target::C),
+			"cpp" | "c++" => Some(Target::Cpp),
+			"rust" | "rs" => Some(Target::Rust),
+			"wat" | "wasm" => Some(Target::Wat),
+			"x86-64" | "x86_64" | "asm" => Some(Target::X86_64),
+			"llvm" | "ll" => Some(Target::Llvm),
+			_ => None,
+		}
+	}
+
+	/* File extension for generated sources */
+	pub fn extension(&self) -> &'static str {
+		match *self {
+			Target::C => "c",
+			Target::Cpp => "cpp",
+			Target::Rust => "rs",
+			Target::Wat => "wat",
+			Target::X86_64 => "s",
+			Target::Llvm => "ll",
+		}
+	}
+}
+
+/* Arithmetic every backend implements the way the interpreter does, see
+ * Instruction::evaluate: signed 64-bit values that wrap around on overflow,
+ * division truncating toward zero with the remainder taking the sign of the
+ * dividend, and the most negative value divided by -1 wrapping back to itself
+ * with remainder 0. Dividing by zero stops the generated program with this
+ * message on stderr and the exit status `jaz run` uses for runtime errors.
+ * Whatever the program printed before is flushed ahead of the message, so it
+ * comes out first even when stdout is buffered into a pipe or a file. The
+ * WebAssembly backend leaves that to the host's `fail` import.
+ */
+pub const DIVISION_BY_ZERO: &str = "error: division by zero";
+pub const RUNTIME_ERROR_STATUS: i32 = 3;
+
+/* Generates source code for `program` in the target language */
+pub fn generate(program: &Program, target: Target) -> Result<String, CodegenError> {
+	match target {
+		Target::C => clike::generate(program, Dialect::C),
+		Target::Cpp => clike::generate(program, Dialect::Cpp),
+		Target::Rust => rust::generate(program),
+		Target::Wat => wat::generate(program),
+		Target::X86_64 => x86_64::generate(program),
+		Target::Llvm => llvm::generate(program),
+	}
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum BinaryOp {
+	Add,
+	Sub,
+	Mul,
+	Div,
+	Rem,
+	And,
+	Or,
+	NotEqual,
+	LessEqual,
+	GreaterEqual,
+	Less,
+	Greater,
+	Equal,
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum Expr {
+	Constant(i64),
+	Variable(Variable),
+	Slot(usize),			/* Stack slot, numbered by depth */
+	Binary(BinaryOp, Box<Expr>, Box<Expr>),
+	Not(Box<Expr>),
+}
+
+impl Expr {
+	/* Whether `op` is applied anywhere in the expression */
+	pub fn applies(&self, op: BinaryOp) -> bool {
+		match *self {
+			Expr::Binary(applied, ref left, ref right) => applied == op || left.applies(op) || right.applies(op),
+			Expr::Not(ref operand) => operand.applies(op),
+			Expr::Constant(_) | Expr::Variable(_) | Expr::Slot(_) => false,
+		}
+	}
+
+	fn reads(&self, variable: &Variable) -> bool {
+		match *self {
+			Expr::Variable(ref read) => read == variable,
+			Expr::Binary(_, ref left, ref right) => left.reads(variable) || right.reads(variable),
+			Expr::Not(ref operand) => operand.reads(variable),
+			Expr::Constant(_) | Expr::Slot(_) => false,
+		}
+	}
+
+	/* Division by zero is a runtime error, so a dropped division must still run */
+	fn divides(&self) -> bool {
+		match *self {
+			Expr::Binary(BinaryOp::Div, _, _) | Expr::Binary(BinaryOp::Rem, _, _) => true,
+			Expr::Binary(_, ref left, ref right) => left.divides() || right.divides(),
+			Expr::Not(ref operand) => operand.divides(),
+			Expr::Constant(_) | Expr::Variable(_) | Expr::Slot(_) => false,
+		}
+	}
+
+	/* Cheap enough to duplicate instead of spilling on copy */
+	fn is_simple(&self) -> bool {
+		matches!(*self, Expr::Constant(_) | Expr::Variable(_) | Expr::Slot(_))
+	}
+}
+
+/* How a subroutine uses a variable of its own frame, which decides how it is passed */
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Mode {
+	In,		/* Read before it is written, no caller looks at what it writes: by value */
+	Out,	/* Written before it is read, a caller uses the result: by pointer */
+	InOut,	/* Read first and written, a caller uses the result: by pointer */
+	Local,	/* Written before it is read, no caller looks at it: not passed at all */
+}
+
+impl Mode {
+	pub fn by_pointer(self) -> bool {
+		matches!(self, Mode::Out | Mode::InOut)
+	}
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Parameter {
+	pub name: String,
+	pub mode: Mode,
+}
+
+/* A caller's variable bound to a parameter at one call site */
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum Argument {
+	Value(Variable),
+	Reference(Variable),
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum Statement {
+	Assign(Variable, Expr),			/* variable = value */
+	Spill(usize, Expr),				/* stack slot = value */
+	Evaluate(Expr),					/* value computed for its side effects only */
+	Print(Expr),
+	Show(String),
+	Call(String, Vec<Argument>),	/* One argument per parameter the callee does not keep local */
+	Label(String),
+	Goto(String),
+	Branch(Expr, bool, String),		/* jump when the value is non-zero (true) or zero (false) */
+	Return,
+	Halt,
+}
+
+impl Statement {
+	/* The value the statement computes, if any */
+	pub fn value(&self) -> Option<&Expr> {
+		match *self {
+			Statement::Assign(_, ref value) | Statement::Spill(_, ref value) | Statement::Evaluate(ref value)
+			| Statement::Print(ref value) | Statement::Branch(ref value, _, _) => Some(value),
+			Statement::Show(_) | Statement::Call(..) | Statement::Label(_) | Statement::Goto(_)
+			| Statement::Return | Statement::Halt => None,
+		}
+	}
+}
+
+pub struct Function {
+	pub label: Option<String>,		/* None for the program entry */
+	pub slots: usize,				/* Number of stack slots the body uses */
+	pub parameters: Vec<Parameter>,	/* A subroutine's own frame, sorted by name */
+	pub locals: Vec<Variable>,		/* Variables of the begin blocks in the body */
+	pub body: Vec<Statement>,
+}
+
+impl Function {
+	/* Parameters passed by pointer are read and written through it */
+	pub fn is_pointer(&self, variable: &Variable) -> bool {
+		self.label.is_some() && variable.frame == Frame::Own && self.parameters.iter()
+			.any(|parameter| parameter.name == variable.name && parameter.mode.by_pointer())
+	}
+}
+
+pub struct Module {
+	pub variables: Vec<String>,		/* The program entry's own frame, sorted */
+	pub functions: Vec<Function>,	/* Program entry first, then subroutines */
+}
+
+impl Module {
+	/* Whether any function applies `op`, so backends only emit the helpers they need */
+	pub fn applies(&self, op: BinaryOp) -> bool {
+		self.functions.iter()
+			.flat_map(|function| &function.body)
+			.any(|statement| statement.value().is_some_and(|value| value.applies(op)))
+	}
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum CodegenError {
+	UndefinedLabel(String),
+	StackUnderflow(usize),			/* Instruction index that pops an empty stack */
+	ExpectedInteger(usize),			/* An address is used as a number */
+	ExpectedAddress(usize),			/* := without an lvalue below the value */
+	StackMismatch(usize),			/* Paths reach an instruction with different stacks */
+	ReturnOutsideSubroutine(usize),
+	UnbalancedSubroutine(String),	/* Subroutine leaves values on the stack */
+	ScopeMismatch(usize),			/* Paths reach an instruction inside different begin blocks */
+	UnmatchedEnd(usize),			/* end reached with no begin block open */
+	UnclosedBegin(usize),			/* return inside a begin block of the subroutine */
+	InvalidOutput(String),			/* The backend wrote something its own validator rejects */
+}
+
+impl fmt::Display for CodegenError {
+	fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+		match *self {
+			CodegenError::UndefinedLabel(ref label) => write!(f, "jump to undefined label `{}`", label),
+			CodegenError::StackUnderflow(pc) => write!(f, "stack underflow at instruction {}", pc),
+			CodegenError::ExpectedInteger(pc) => write!(f, "expected an integer on the stack at instruction {}", pc),
+			CodegenError::ExpectedAddress(pc) => write!(f, "expected an lvalue on the stack at instruction {}", pc),
+			CodegenError::StackMismatch(pc) => write!(f, "paths reach instruction {} with different stacks", pc),
+			CodegenError::ReturnOutsideSubroutine(pc) => write!(f, "return outside of a subroutine at instruction {}", pc),
+			CodegenError::UnbalancedSubroutine(ref label) => write!(f, "subroutine `{}` does not leave the stack as it found it", label),
+			CodegenError::ScopeMismatch(pc) => write!(f, "paths reach instruction {} inside different begin blocks", pc),
+			CodegenError::UnmatchedEnd(pc) => write!(f, "end without an open begin block at instruction {}", pc),
+			CodegenError::UnclosedBegin(pc) => write!(f, "return with a begin block still open at instruction {}", pc),
+			CodegenError::InvalidOutput(ref why) => write!(f, "generated code is invalid: {}", why),
+		}
+	}
+}
+
+/* The problems a single instruction's stack effect can have, see analysis::stack::step */
+impl From<StackError> for CodegenError {
+	fn from(problem: StackError) -> CodegenError {
+		match problem {
+			StackError::Underflow(index) => CodegenError::StackUnderflow(index),
+			StackError::ExpectedInteger(index) => CodegenError::ExpectedInteger(index),
+			StackError::ExpectedAddress(index) => CodegenError::ExpectedAddress(index),
+			_ => unreachable!(),
+		}
+	}
+}
+
+impl CodegenError {
+	pub fn diagnostic(&self, program: &Program) -> Diagnostic {
+		match *self {
+			CodegenError::UndefinedLabel(ref label) => {
+				let diagnostic = Diagnostic::error(format!("jump to undefined label `{}`", label));
+				match program.code().iter().position(|instruction| instruction.jumps_to(label)) {
+					Some(index) => diagnostic.at(program.span(index)).label("no `label` with this name"),
+					None => diagnostic,
+				}
+			}
+			CodegenError::StackUnderflow(pc) => Diagnostic::error("stack underflow").at(program.span(pc)).label("pops an empty stack"),
+			CodegenError::ExpectedInteger(pc) => Diagnostic::error("expected an integer on the stack")
+				.at(program.span(pc))
+				.label("finds an lvalue here")
+				.help("use `rvalue` to push the contents of a variable"),
+			CodegenError::ExpectedAddress(pc) => Diagnostic::error("expected an lvalue on the stack")
+				.at(program.span(pc))
+				.label("nothing to assign to")
+				.note("`:=` stores the top of the stack into the `lvalue` below it"),
+			CodegenError::StackMismatch(pc) => Diagnostic::error("paths reach this instruction with different stacks")
+				.at(program.span(pc))
+				.note("every jump to a label must leave the stack as deep as falling through to it does"),
+			CodegenError::ReturnOutsideSubroutine(pc) => Diagnostic::error("return outside of a subroutine").at(program.span(pc)),
+			CodegenError::UnbalancedSubroutine(ref label) => {
+				let diagnostic = Diagnostic::error(format!("subroutine `{}` does not leave the stack as it found it", label))
+					.note("subroutines communicate through variables, not the stack");
+				match program.target(label) {
+					Some(index) => diagnostic.at(program.span(index)).label("subroutine starts here"),
+					None => diagnostic,
+				}
+			}
+			CodegenError::ScopeMismatch(pc) => Diagnostic::error("paths reach this instruction inside different begin blocks")
+				.at(program.span(pc))
+				.note("lvalue and rvalue refer to different frames inside and outside a begin block"),
+			CodegenError::UnmatchedEnd(pc) => Diagnostic::error("end without an open begin block")
+				.at(program.span(pc))
+				.label("control can reach this `end` without passing its `begin`"),
+			CodegenError::UnclosedBegin(pc) => Diagnostic::error("return with a begin block still open")
+				.at(program.span(pc))
+				.help("close every `begin` with its `end` before returning"),
+			CodegenError::InvalidOutput(ref why) => Diagnostic::error(format!("generated code is invalid: {}", why))
+				.note("this is a bug in the compiler, not in the program"),
+		}
+	}
+}
+
+/* Compile time view of a stack entry, as the values lowering produces it */
+#[derive(Clone, Debug, PartialEq, Eq)]
+enum Entry {
+	Value(Expr),
+	Address(Variable),
+}
+
+/* What one function does with the stack and with frames, worked out before emitting it */
+struct Analysis {
+	label: Option<String>,
+	entry: usize,
+	states: BTreeMap<usize, (Vec<Shape>, Scope)>,	/* Before every reachable instruction */
+	targets: HashSet<usize>,						/* Instructions jumps land on */
+	references: BTreeSet<Variable>,					/* Variables the body names itself */
+	calls: Vec<(String, Frame)>,					/* Callees, and the frame each one runs in */
+}
+
+pub fn lower(program: &Program) -> Result<Module, CodegenError> {
+	let code = program.code();
+
+	/* The graph leaves out jumps to labels that do not exist */
+	for instruction in code {
+		match *instruction {
+			Instruction::Goto(ref label) | Instruction::GoFalse(ref label)
+			| Instruction::GoTrue(ref label) | Instruction::Call(ref label) => {
+				program.target(label).ok_or_else(|| CodegenError::UndefinedLabel(label.clone()))?;
+			}
+			_ => {}
+		}
+	}
+	let cfg = Cfg::build(program);
+	let analyses: Vec<Analysis> = cfg.functions().iter()
+		.map(|function| analyse_function(program, &cfg, function.label.clone(), function.entry))
+		.collect::<Result<_, _>>()?;
+
+	/* A callee's own frame is whichever frame it is called in, so the variables it
+	 * uses there belong to that frame of the caller too. Recursion needs repeating
+	 * this until nothing changes.
+	 */
+	let position: HashMap<Option<String>, usize> = analyses.iter().enumerate()
+		.map(|(position, analysis)| (analysis.label.clone(), position))
+		.collect();
+	let mut frames: Vec<BTreeMap<Frame, BTreeSet<String>>> = analyses.iter().map(|analysis| {
+		let mut frames: BTreeMap<Frame, BTreeSet<String>> = BTreeMap::new();
+		for variable in &analysis.references {
+			frames.entry(variable.frame).or_default().insert(variable.name.clone());
+		}
+		frames
+	}).collect();
+	let mut changed = true;
+	while changed {
+		changed = false;
+		for (caller, analysis) in analyses.iter().enumerate() {
+			for &(ref callee, frame) in &analysis.calls {
+				let inherited: Vec<String> = frames[position[&Some(callee.clone())]]
+					.get(&Frame::Own)
+					.map_or(Vec::new(), |own| own.iter().cloned().collect());
+				let into = frames[caller].entry(frame).or_default();
+				for name in inherited {
+					changed |= into.insert(name);
+				}
+			}
+		}
+	}
+
+	/* Subroutines take their own frame as parameters, the program entry's is global */
+	let own = |frames: &BTreeMap<Frame, BTreeSet<String>>| -> Vec<String> {
+		frames.get(&Frame::Own).map_or(Vec::new(), |own| own.iter().cloned().collect())
+	};
+	let parameters = signatures(program, &cfg, &analyses, &frames);
+
+	/* Variables that callees keep local need no storage in their callers */
+	let frames: Vec<BTreeMap<Frame, BTreeSet<String>>> = analyses.iter().zip(frames).map(|(analysis, mut frames)| {
+		let mut needed = analysis.references.clone();
+		for &(ref callee, frame) in &analysis.calls {
+			needed.extend(parameters[callee].iter()
+				.filter(|parameter| parameter.mode != Mode::Local)
+				.map(|parameter| Variable::new(frame, &parameter.name)));
+		}
+		for (&frame, names) in frames.iter_mut() {
+			names.retain(|name| needed.contains(&Variable::new(frame, name)));
+		}
+		frames
+	}).collect();
+	let parameters: HashMap<String, Vec<Parameter>> = parameters.into_iter().map(|(label, parameters)| {
+		let own = frames[position[&Some(label.clone())]].get(&Frame::Own);
+		let parameters = parameters.into_iter()
+			.filter(|parameter| own.is_some_and(|own| own.contains(&parameter.name)))
+			.collect();
+		(label, parameters)
+	}).collect();
+
+	let mut functions: Vec<Function> = analyses.iter().zip(&frames)
+		.map(|(analysis, frames)| emit_function(program, analysis, frames, &parameters))
+		.collect();
+	if code.is_empty() {
+		functions[0].body.push(Statement::Halt);
+	}
+
+	Ok(Module {
+		variables: own(&frames[0]),
+		functions,
+	})
+}
+
+/* Infers how every subroutine uses each variable of its own frame. Reading it
+ * before writing it makes it an input, and writing it (itself or through a
+ * callee sharing its frame) an output, which only has to reach the caller when
+ * some call site still reads the variable afterwards.
+ */
+fn signatures(
+	program: &Program,
+	cfg: &Cfg,
+	analyses: &[Analysis],
+	frames: &[BTreeMap<Frame, BTreeSet<String>>]
+) -> HashMap<String, Vec<Parameter>> {
+	let code = program.code();
+	let subroutines = || analyses.iter().zip(frames).filter_map(|(analysis, frames)| {
+		analysis.label.as_ref().map(|label| (label, analysis, frames))
+	});
+
+	/* Inputs and outputs feed each other through calls, so they grow to a fixpoint */
+	let mut inputs: HashMap<String, BTreeSet<String>> = HashMap::new();
+	let mut outputs: HashMap<String, BTreeSet<String>> = HashMap::new();
+	let mut changed = true;
+	while changed {
+		changed = false;
+		for (label, analysis, _) in subroutines() {
+			let live = liveness(program, cfg, analysis, &inputs);
+			let read: BTreeSet<String> = live.get(&analysis.entry).into_iter().flatten()
+				.filter(|variable| variable.frame == Frame::Own)
+				.map(|variable| variable.name.clone())
+				.collect();
+			let mut written: BTreeSet<String> = BTreeSet::new();
+			for (&index, (shape, _)) in &analysis.states {
+				if let Instruction::Assign = code[index] {
+					if let Shape::Address(ref variable) = shape[shape.len() - 2] {
+						if variable.frame == Frame::Own {
+							written.insert(variable.name.clone());
+						}
+					}
+				}
+			}
+			for &(ref callee, frame) in &analysis.calls {
+				if frame == Frame::Own {
+					written.extend(outputs.get(callee).into_iter().flatten().cloned());
+				}
+			}
+			if inputs.get(label) != Some(&read) || outputs.get(label) != Some(&written) {
+				inputs.insert(label.clone(), read);
+				outputs.insert(label.clone(), written);
+				changed = true;
+			}
+		}
+	}
+
+	/* A subroutine sharing its caller's frame hands the variables on to whoever called
+	 * that caller, so only the program entry and begin blocks are known to be done with them */
+	let mut observed: HashSet<(String, String)> = HashSet::new();
+	for (analysis, frames) in analyses.iter().zip(frames) {
+		let live = liveness(program, cfg, analysis, &inputs);
+		for (&index, (_, scope)) in &analysis.states {
+			if let Instruction::Call(ref callee) = code[index] {
+				let frame = call_frame(scope);
+				let after = live.get(&(index + 1));
+				for name in frames.get(&frame).into_iter().flatten() {
+					let variable = Variable::new(frame, name);
+					if (frame == Frame::Own && analysis.label.is_some()) || after.is_some_and(|after| after.contains(&variable)) {
+						observed.insert((callee.clone(), name.clone()));
+					}
+				}
+			}
+		}
+	}
+
+	subroutines().map(|(label, _, frames)| {
+		let parameters = frames.get(&Frame::Own).into_iter().flatten().map(|name| {
+			let read = inputs[label].contains(name);
+			let returned = outputs[label].contains(name) && observed.contains(&(label.clone(), name.clone()));
+			let mode = match (read, returned) {
+				(true, true) => Mode::InOut,
+				(true, false) => Mode::In,
+				(false, true) => Mode::Out,
+				(false, false) => Mode::Local,
+			};
+			Parameter { name: name.clone(), mode }
+		}).collect();
+		(label.clone(), parameters)
+	}).collect()
+}
+
+/* Variables whose value may still be read, before every reachable instruction.
+ * Nothing is live once the function halts or returns, and a call reads the
+ * inputs of its callee from the frame it runs in.
+ */
+fn liveness(
+	program: &Program,
+	cfg: &Cfg,
+	analysis: &Analysis,
+	inputs: &HashMap<String, BTreeSet<String>>
+) -> BTreeMap<usize, BTreeSet<Variable>> {
+	let code = program.code();
+	let mut live: BTreeMap<usize, BTreeSet<Variable>> = BTreeMap::new();
+	let mut changed = true;
+	while changed {
+		changed = false;
+		for (&index, (shape, scope)) in analysis.states.iter().rev() {
+			let mut before: BTreeSet<Variable> = BTreeSet::new();
+			for next in cfg.next(index) {
+				before.extend(live.get(&next).into_iter().flatten().cloned());
+			}
+			match code[index] {
+				Instruction::Rvalue(ref name) => {
+					before.insert(Variable::new(resolve(scope, false), name));
+				}
+				Instruction::Assign => {
+					if let Shape::Address(ref variable) = shape[shape.len() - 2] {
+						before.remove(variable);
+					}
+				}
+				Instruction::Begin => before.retain(|variable| variable.frame != Frame::Block(index)),
+				Instruction::Call(ref callee) => {
+					let frame = call_frame(scope);
+					before.extend(inputs.get(callee).into_iter().flatten().map(|name| Variable::new(frame, name)));
+				}
+				_ => {}
+			}
+			if live.get(&index) != Some(&before) {
+				live.insert(index, before);
+				changed = true;
+			}
+		}
+	}
+	live
+}
+
+fn binary_op(instruction: &Instruction) -> Option<BinaryOp> {
+	match *instruction {
+		Instruction::Add => Some(BinaryOp::Add),
+		Instruction::Sub => Some(BinaryOp::Sub),
+		Instruction::Mul => Some(BinaryOp::Mul),
+		Instruction::Div => Some(BinaryOp::Div),
+		Instruction::Rem => Some(BinaryOp::Rem),
+		Instruction::And => Some(BinaryOp::And),
+		Instruction::Or => Some(BinaryOp::Or),
+		Instruction::NotEqual => Some(BinaryOp::NotEqual),
+		Instruction::LessEqual => Some(BinaryOp::LessEqual),
+		Instruction::GreaterEqual => Some(BinaryOp::GreaterEqual),
+		Instruction::Less => Some(BinaryOp::Less),
+		Instruction::Greater => Some(BinaryOp::Greater),
+		Instruction::Equal => Some(BinaryOp::Equal),
+		_ => None,
+	}
+}
+
+/* Forward pass: the stack shape and open begin blocks before every reachable instruction */
+fn analyse_function(program: &Program, cfg: &Cfg, label: Option<String>, entry: usize) -> Result<Analysis, CodegenError> {
+	let code = program.code();
+	let mut states: BTreeMap<usize, (Vec<Shape>, Scope)> = BTreeMap::new();
+	let mut targets: HashSet<usize> = HashSet::new();
+	let mut references: BTreeSet<Variable> = BTreeSet::new();
+	let mut calls: Vec<(String, Frame)> = Vec::new();
+
+	let mut worklist: Vec<(usize, Vec<Shape>, Scope)> = Vec::new();
+	if entry < code.len() {
+		worklist.push((entry, Vec::new(), Vec::new()));
+	}
+	while let Some((index, stack, scope)) = worklist.pop() {
+		if let Some((known_stack, known_scope)) = states.get(&index) {
+			if *known_stack != stack {
+				return Err(CodegenError::StackMismatch(index));
+			}
+			if *known_scope != scope {
+				return Err(CodegenError::ScopeMismatch(index));
+			}
+			continue;
+		}
+		states.insert(index, (stack.clone(), scope.clone()));
+
+		let mut after = stack;
+		step(&code[index], index, &scope, &mut after)?;
+		let mut scope_after = scope.clone();
+		match code[index] {
+			Instruction::Rvalue(ref name) => {
+				references.insert(Variable::new(resolve(&scope, false), name));
+			}
+			Instruction::Lvalue(ref name) => {
+				references.insert(Variable::new(resolve(&scope, true), name));
+			}
+			Instruction::Begin => scope_after.push((index, Phase::Setup)),
+			Instruction::End => {
+				scope_after.pop().ok_or(CodegenError::UnmatchedEnd(index))?;
+			}
+			Instruction::Call(ref callee) => {
+				calls.push((callee.clone(), call_frame(&scope)));
+				if let Some(block) = scope_after.last_mut() {
+					block.1 = Phase::Returned;
+				}
+			}
+			Instruction::Return => {
+				if label.is_none() {
+					return Err(CodegenError::ReturnOutsideSubroutine(index));
+				}
+				if !after.is_empty() {
+					return Err(CodegenError::UnbalancedSubroutine(label.clone().unwrap_or_default()));
+				}
+				if !scope.is_empty() {
+					return Err(CodegenError::UnclosedBegin(index));
+				}
+			}
+			Instruction::Goto(ref target) | Instruction::GoFalse(ref target) | Instruction::GoTrue(ref target) => {
+				targets.extend(program.target(target));
+			}
+			_ => {}
+		}
+		/* A subroutine running past the end of the program stops it like halt */
+		if cfg.exits(index) && code[index] != Instruction::Halt && label.is_some() && !after.is_empty() {
+			return Err(CodegenError::UnbalancedSubroutine(label.clone().unwrap_or_default()));
+		}
+		for next in cfg.next(index) {
+			worklist.push((next, after.clone(), scope_after.clone()));
+		}
+	}
+
+	Ok(Analysis { label, entry, states, targets, references, calls })
+}
+
+/* Emission pass, in source order */
+fn emit_function(
+	program: &Program,
+	analysis: &Analysis,
+	frames: &BTreeMap<Frame, BTreeSet<String>>,
+	parameters: &HashMap<String, Vec<Parameter>>
+) -> Function {
+	let code = program.code();
+	let targets = &analysis.targets;
+	let mut lowering = Lowering { body: Vec::new(), stack: Vec::new() };
+	let mut falls_through = false;
+	for (&index, (shape, scope)) in &analysis.states {
+		let instruction = &code[index];
+		if !falls_through || targets.contains(&index) {
+			if falls_through {
+				lowering.flush();
+			}
+			/* Control arrives from a jump, values wait in their slots */
+			lowering.stack = shape.iter().enumerate().map(|(slot, entry)| match *entry {
+				Shape::Value => Entry::Value(Expr::Slot(slot)),
+				Shape::Address(ref variable) => Entry::Address(variable.clone()),
+			}).collect();
+		}
+
+		match *instruction {
+			Instruction::Push(constant) => lowering.push(Expr::Constant(constant)),
+			Instruction::Rvalue(ref name) => lowering.push(Expr::Variable(Variable::new(resolve(scope, false), name))),
+			Instruction::Lvalue(ref name) => lowering.stack.push(Entry::Address(Variable::new(resolve(scope, true), name))),
+			Instruction::Pop => {
+				if let Some(Entry::Value(value)) = lowering.stack.pop() {
+					if value.divides() {
+						lowering.body.push(Statement::Evaluate(value));
+					}
+				}
+			}
+			Instruction::Assign => {
+				let value = lowering.pop_value();
+				let variable = match lowering.stack.pop() {
+					Some(Entry::Address(variable)) => variable,
+					_ => unreachable!(),
+				};
+				lowering.assign(variable, value);
+			}
+			Instruction::Copy => {
+				let top = lowering.stack.last().cloned().unwrap();
+				let top = match top {
+					Entry::Value(ref expr) if !expr.is_simple() => {
+						lowering.flush();
+						lowering.stack.last().cloned().unwrap()
+					}
+					other => other,
+				};
+				lowering.stack.push(top);
+			}
+			Instruction::Label(ref name) => {
+				if targets.contains(&index) {
+					lowering.body.push(Statement::Label(name.clone()));
+				}
+			}
+			Instruction::Goto(ref name) => {
+				lowering.flush();
+				lowering.body.push(Statement::Goto(name.clone()));
+			}
+			Instruction::GoFalse(ref name) | Instruction::GoTrue(ref name) => {
+				let condition = lowering.pop_value();
+				lowering.flush();
+				let jump_if = matches!(*instruction, Instruction::GoTrue(_));
+				lowering.body.push(Statement::Branch(condition, jump_if, name.clone()));
+			}
+			Instruction::Halt => {
+				lowering.flush_divisions();
+				lowering.body.push(Statement::Halt);
+			}
+			Instruction::Return => {
+				lowering.flush_divisions();
+				lowering.body.push(Statement::Return);
+			}
+			Instruction::Not => {
+				let value = lowering.pop_value();
+				lowering.push(Expr::Not(Box::new(value)));
+			}
+			Instruction::Print => {
+				let value = lowering.pop_value();
+				lowering.flush_divisions();
+				lowering.body.push(Statement::Print(value.clone()));
+				lowering.push(value);
+			}
+			Instruction::Show(ref text) => {
+				lowering.flush_divisions();
+				lowering.body.push(Statement::Show(text.clone()));
+			}
+			Instruction::Call(ref name) => {
+				/* The callee may assign any variable a pending value reads */
+				lowering.flush();
+				let frame = call_frame(scope);
+				let arguments = parameters[name].iter().filter_map(|parameter| {
+					let variable = Variable::new(frame, &parameter.name);
+					match parameter.mode {
+						Mode::In => Some(Argument::Value(variable)),
+						Mode::Out | Mode::InOut => Some(Argument::Reference(variable)),
+						Mode::Local => None,
+					}
+				}).collect();
+				lowering.body.push(Statement::Call(name.clone(), arguments));
+			}
+			Instruction::Begin => {
+				/* Every call gets a fresh frame, its variables start at zero */
+				for name in frames.get(&Frame::Block(index)).into_iter().flatten() {
+					lowering.assign(Variable::new(Frame::Block(index), name), Expr::Constant(0));
+				}
+			}
+			Instruction::End => {}
+			ref operator => {
+				let op = binary_op(operator).unwrap();
+				let right = lowering.pop_value();
+				let left = lowering.pop_value();
+				lowering.push(Expr::Binary(op, Box::new(left), Box::new(right)));
+			}
+		}
+
+		falls_through = !matches!(*instruction, Instruction::Goto(_) | Instruction::Halt | Instruction::Return);
+		/* Running past the last instruction stops the program */
+		if falls_through && index + 1 == code.len() {
+			lowering.flush_divisions();
+			lowering.body.push(Statement::Halt);
+		}
+	}
+
+	/* Slots are only read after a spill wrote them, so spills decide the count */
+	let slots = lowering.body.iter().filter_map(|statement| match *statement {
+		Statement::Spill(slot, _) => Some(slot + 1),
+		_ => None,
+	}).max().unwrap_or(0);
+
+	let label = analysis.label.clone();
+	let parameters = match label {
+		Some(ref label) => parameters[label].clone(),
+		None => Vec::new(),
+	};
+	let locals = frames.iter()
+		.filter(|&(frame, _)| *frame != Frame::Own)
+		.flat_map(|(&frame, names)| names.iter().map(move |name| Variable::new(frame, name)))
+		.collect();
+	Function { label, slots, parameters, locals, body: lowering.body }
+}
+
+struct Lowering {
+	body: Vec<Statement>,
+	stack: Vec<Entry>,
+}
+
+impl Lowering {
+	fn push(&mut self, value: Expr) {
+		self.stack.push(Entry::Value(value));
+	}
+
+	fn pop_value(&mut self) -> Expr {
+		match self.stack.pop() {
+			Some(Entry::Value(value)) => value,
+			_ => unreachable!(),
+		}
+	}
+
+	/* Pending reads of the variable must see its old value */
+	fn assign(&mut self, variable: Variable, value: Expr) {
+		if self.stack.iter().any(|entry| match *entry {
+			Entry::Value(ref expr) => expr.reads(&variable),
+			Entry::Address(_) => false,
+		}) {
+			self.flush();
+		}
+		self.body.push(Statement::Assign(variable, value));
+	}
+
+	/* A pending division must fail before any output that follows it, and
+	 * before the program stops. Only a full flush keeps the slots consistent.
+	 */
+	fn flush_divisions(&mut self) {
+		if self.stack.iter().any(|entry| match *entry {
+			Entry::Value(ref expr) => expr.divides(),
+			Entry::Address(_) => false,
+		}) {
+			self.flush();
+		}
+	}
+
+	/* Store every pending value in its slot, lowest slot first. An expression
+	 * only refers to slots at or above its own depth, or to a lower slot that
+	 * is already in place, so writing upwards never clobbers a pending read.
+	 */
+	fn flush(&mut self) {
+		for (slot, entry) in self.stack.iter_mut().enumerate() {
+			if let Entry::Value(ref mut value) = *entry {
+				if *value != Expr::Slot(slot) {
+					let pending = ::std::mem::replace(value, Expr::Slot(slot));
+					self.body.push(Statement::Spill(slot, pending));
+				}
+			}
+		}
+	}
+}
+
+/* Maps jaz names onto identifiers that are valid and unique in a target */
+pub struct Names {
+	variables: HashMap<(Option<String>, Variable), String>,	/* Keyed by function label */
+	functions: HashMap<String, String>,
+	keywords: HashSet<String>,
+}
+
+impl Names {
+	pub fn new(module: &Module, keywords: &[&str]) -> Names {
+		let mut names = Names {
+			variables: HashMap::new(),
+			functions: HashMap::new(),
+			keywords: keywords.iter().map(|word| word.to_string()).collect(),
+		};
+		let mut taken: HashSet<String> = names.keywords.clone();
+		for variable in &module.variables {
+			let identifier = Names::claim(&mut taken, variable);
+			names.variables.insert((None, Variable::new(Frame::Own, variable)), identifier);
+		}
+		let globals = taken.clone();
+		for function in &module.functions {
+			if let Some(ref label) = function.label {
+				let identifier = Names::claim(&mut taken, &Names::label_identifier(label));
+				names.functions.insert(label.clone(), identifier);
+			}
+		}
+
+		/* Subroutines never see the globals, so their parameters may shadow them */
+		for function in &module.functions {
+			let mut local: HashSet<String> = if function.label.is_some() {
+				taken.difference(&globals).cloned().chain(names.keywords.iter().cloned()).collect()
+			} else {
+				taken.clone()
+			};
+			for parameter in &function.parameters {
+				let identifier = Names::claim(&mut local, &parameter.name);
+				names.variables.insert((function.label.clone(), Variable::new(Frame::Own, &parameter.name)), identifier);
+			}
+
+			/* Begin block variables are suffixed with the block's number in the function */
+			let mut blocks: Vec<Frame> = function.locals.iter().map(|variable| variable.frame).collect();
+			blocks.dedup();
+			for variable in &function.locals {
+				let number = blocks.iter().position(|&frame| frame == variable.frame).unwrap() + 1;
+				let identifier = Names::claim(&mut local, &format!("{}_{}", variable.name, number));
+				names.variables.insert((function.label.clone(), variable.clone()), identifier);
+			}
+		}
+		names
+	}
+
+	/* Numeric labels (label 2000) are not identifiers on their own */
+	fn label_identifier(label: &str) -> String {
+		if LabelKind::of(label) == LabelKind::Numeric {
+			format!("label_{}", label)
+		} else {
+			label.to_string()
+		}
+	}
+
+	fn claim(taken: &mut HashSet<String>, name: &str) -> String {
+		let mut identifier = name.to_string();
+		while taken.contains(&identifier) {
+			identifier.push('_');
+		}
+		taken.insert(identifier.clone());
+		identifier
+	}
+
+	/* Identifier of a variable as `function` refers to it */
+	pub fn variable(&self, function: &Function, variable: &Variable) -> &str {
+		&self.variables[&(function.label.clone(), variable.clone())]
+	}
+
+	pub fn function(&self, label: &str) -> &str {
+		&self.functions[label]
+	}
+
+	/* Jump labels live in their own namespace, only keywords need escaping */
+	pub fn label(&self, label: &str) -> String {
+		let mut identifier = Names::label_identifier(label);
+		while self.keywords.contains(&identifier) {
+			identifier.push('_');
+		}
+		identifier
+	}
+
+	pub fn slot(&self, slot: usize) -> String {
+		format!("_s{}", slot)
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use std::env;
+	use std::fs::{self, File};
+	use std::path::Path;
+	use std::process::{self, Command};
+
+	use super::{generate, Target, DIVISION_BY_ZERO, RUNTIME_ERROR_STATUS};
+	use interpreter::Interpreter;
+
+	/* Exit status and everything written to stdout and stderr together, None when the tool is missing */
+	fn run(command: &mut Command, output: &Path) -> Option<(i32, String)> {
+		let file = File::create(output).unwrap();
+		let status = command.stdout(file.try_clone().unwrap()).stderr(file).status().ok()?;
+		Some((status.code().unwrap_or(-1), fs::read_to_string(output).unwrap()))
+	}
+
+	/* Builds the generated source into an executable, None when this machine has no toolchain for it */
+	fn build(target: Target, source: &Path, binary: &Path, log: &Path) -> Option<()> {
+		let (code, output) = match target {
+			Target::C => run(Command::new("gcc").arg("-std=c99").arg("-o").arg(binary).arg(source), log)?,
+			Target::Cpp => run(Command::new("g++").arg("-o").arg(binary).arg(source), log)?,
+			Target::Rust => run(Command::new("rustc").arg("-o").arg(binary).arg(source), log)?,
+			Target::X86_64 => run(Command::new("cc").arg("-o").arg(binary).arg(source), log)?,
+			Target::Llvm => {
+				/* LLVM 14 only reads opaque pointers when asked to */
+				let assembly = source.with_extension("s");
+				let llc = |flags: &[&str]| run(Command::new("llc").args(flags).arg("-relocation-model=pic").arg("-o").arg(&assembly).arg(source), log);
+				match llc(&[])? {
+					(0, _) => {}
+					_ => {
+						let (code, output) = llc(&["-opaque-pointers"])?;
+						assert_eq!(code, 0, "llc rejects the IR:\n{}", output);
+					}
+				}
+				run(Command::new("cc").arg("-o").arg(binary).arg(&assembly), log)?
+			}
+			Target::Wat => return None,
+		};
+		assert_eq!(code, 0, "{:?} output does not build:\n{}", target, output);
+		Some(())
+	}
+
+	#[test]
+	fn division_by_zero_comes_after_earlier_output() {
+		let programs = [
+			"show before\npush 7\nprint\npop\npush 1\npush 0\n/\nprint\nhalt\n",
+			"show before\nlvalue x\npush 1\n:=\ncall f\nshow after\nhalt\nlabel f\nshow in f\nrvalue x\nprint\npush 0\ndiv\npop\nreturn\n",
+		];
+		let directory = env::temp_dir().join(format!("jaz-division-{}", process::id()));
+		fs::create_dir_all(&directory).unwrap();
+		for (number, source) in programs.iter().enumerate() {
+			let program = ::parse(source).unwrap();
+			let mut expected: Vec<u8> = Vec::new();
+			assert!(Interpreter::new(&program).run(&mut expected).is_err());
+			let expected = format!("{}{}\n", String::from_utf8(expected).unwrap(), DIVISION_BY_ZERO);
+
+			for &target in &[Target::C, Target::Cpp, Target::Rust, Target::X86_64, Target::Llvm] {
+				let name = format!("program{}_{}", number, target.extension());
+				let source = directory.join(&name).with_extension(target.extension());
+				let binary = directory.join(&name);
+				fs::write(&source, generate(&program, target).unwrap()).unwrap();
+				if build(target, &source, &binary, &directory.join("build.log")).is_none() {
+					eprintln!("note: no toolchain for {:?}, not running it", target);
+					continue;
+				}
+				let output = run(&mut Command::new(&binary), &directory.join("run.log")).unwrap();
+				assert_eq!(output, (RUNTIME_ERROR_STATUS, expected.clone()), "{:?} for:\n{}", target, source.display());
+			}
+		}
+		fs::remove_dir_all(&directory).unwrap();
+	}
+}
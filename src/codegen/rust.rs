@@ -0,0 +1,303 @@
+/* Rust Backend
+ * Writes a self-contained Rust source file. The program entry's own frame
+ * becomes local variables of main, since only main refers to it, and
+ * subroutines take `&mut i64` for the variables they hand back. Comparisons
+ * and logic produce bool in Rust, so expressions are written either as a
+ * value or as a condition. Rust has no goto: a function whose jumps did not
+ * all structure into loops and conditionals runs as a state machine instead,
+ * one match arm per run of statements between labels.
+ */
+use codegen::structure::{gives_up, pieces, structure, Structured};
+use codegen::{lower, Argument, BinaryOp, CodegenError, Expr, Frame, Function, Mode, Module, Names, Statement, Variable};
+use codegen::{DIVISION_BY_ZERO, RUNTIME_ERROR_STATUS};
+use parsetree::Program;
+
+/* Rust keywords, plus the names the generated code itself relies on */
+const RESERVED: &[&str] = &[
+	"_", "abstract", "as", "async", "await", "become", "box", "break", "const", "continue",
+	"crate", "do", "dyn", "else", "enum", "extern", "false", "final", "fn", "for", "gen",
+	"if", "impl", "in", "let", "loop", "macro", "match", "mod", "move", "mut", "override",
+	"priv", "pub", "ref", "return", "self", "Self", "static", "struct", "super", "trait",
+	"true", "try", "type", "typeof", "unsafe", "unsized", "use", "virtual", "where",
+	"while", "yield", "main", "std", "i64", "pc", "jaz_div", "jaz_rem",
+	"Some", "None", "Ok", "Err",
+];
+
+pub fn generate(program: &Program) -> Result<String, CodegenError> {
+	let module = lower(program)?;
+	let names = Names::new(&module, RESERVED);
+	let mut out = String::new();
+
+	/* Generated code assigns values it never reads, keeps every variable mutable and parenthesizes freely */
+	out.push_str("#![allow(unused_assignments, unused_mut, unused_variables, unused_parens, unreachable_code, dead_code)]\n");
+	write_arithmetic(&mut out, &module);
+
+	for function in &module.functions {
+		out.push('\n');
+		write_function(&mut out, &module, function, &names);
+	}
+
+	Ok(out)
+}
+
+/* Division helpers, see codegen::DIVISION_BY_ZERO. The wrapping methods already
+ * give the most negative value divided by -1 the result the interpreter does.
+ */
+fn write_arithmetic(out: &mut String, module: &Module) {
+	for &(op, name, method) in &[(BinaryOp::Div, "jaz_div", "wrapping_div"), (BinaryOp::Rem, "jaz_rem", "wrapping_rem")] {
+		if module.applies(op) {
+			out.push_str(&format!("\nfn {}(a: i64, b: i64) -> i64 {{\n", name));
+			out.push_str(&format!(
+				"\tif b == 0 {{\n\t\teprintln!({});\n\t\tstd::process::exit({});\n\t}}\n",
+				string_literal(DIVISION_BY_ZERO), RUNTIME_ERROR_STATUS
+			));
+			out.push_str(&format!("\ta.{}(b)\n}}\n", method));
+		}
+	}
+}
+
+/* Outputs are mutable references to the caller's variables, inputs are copies */
+fn signature(function: &Function, names: &Names) -> String {
+	match function.label {
+		Some(ref label) => {
+			let parameters: Vec<String> = function.parameters.iter()
+				.filter(|parameter| parameter.mode != Mode::Local)
+				.map(|parameter| {
+					let identifier = names.variable(function, &Variable::new(Frame::Own, &parameter.name));
+					if parameter.mode.by_pointer() {
+						format!("{}: &mut i64", identifier)
+					} else {
+						format!("mut {}: i64", identifier)
+					}
+				})
+				.collect();
+			format!("pub fn {}({})", names.function(label), parameters.join(", "))
+		}
+		None => "pub fn main()".to_string(),
+	}
+}
+
+fn write_function(out: &mut String, module: &Module, function: &Function, names: &Names) {
+	let context = Context { function, names };
+	out.push_str(&format!("{} {{\n", signature(function, names)));
+
+	let mut variables: Vec<String> = Vec::new();
+	if function.label.is_none() {
+		variables.extend(module.variables.iter().map(|name| names.variable(function, &Variable::new(Frame::Own, name)).to_string()));
+	}
+	variables.extend(function.parameters.iter()
+		.filter(|parameter| parameter.mode == Mode::Local)
+		.map(|parameter| names.variable(function, &Variable::new(Frame::Own, &parameter.name)).to_string()));
+	variables.extend(function.locals.iter().map(|local| names.variable(function, local).to_string()));
+	variables.extend((0..function.slots).map(|slot| names.slot(slot)));
+	for variable in &variables {
+		out.push_str(&format!("\tlet mut {}: i64 = 0;\n", variable));
+	}
+
+	let structured = structure(&function.body);
+	if gives_up(&structured) {
+		context.state_machine(out, &function.body);
+	} else {
+		context.block(out, &structured, 1);
+	}
+	out.push_str("}\n");
+}
+
+/* The function whose body is being written, variables are spelled differently in each */
+struct Context<'a> {
+	function: &'a Function,
+	names: &'a Names,
+}
+
+impl<'a> Context<'a> {
+	fn block(&self, out: &mut String, items: &[Structured], depth: usize) {
+		let indent = "\t".repeat(depth);
+		for item in items {
+			match *item {
+				Structured::Simple(ref statement) => out.push_str(&format!("{}{}\n", indent, self.statement(statement))),
+				Structured::If(ref condition, ref then, ref otherwise) => {
+					out.push_str(&format!("{}if {} {{\n", indent, self.condition(condition)));
+					self.block(out, then, depth + 1);
+					if !otherwise.is_empty() {
+						out.push_str(&format!("{}}} else {{\n", indent));
+						self.block(out, otherwise, depth + 1);
+					}
+					out.push_str(&format!("{}}}\n", indent));
+				}
+				Structured::While(ref condition, ref body) => {
+					out.push_str(&format!("{}while {} {{\n", indent, self.condition(condition)));
+					self.block(out, body, depth + 1);
+					out.push_str(&format!("{}}}\n", indent));
+				}
+				Structured::Loop(ref body) => {
+					out.push_str(&format!("{}loop {{\n", indent));
+					self.block(out, body, depth + 1);
+					out.push_str(&format!("{}}}\n", indent));
+				}
+				Structured::Break => out.push_str(&format!("{}break;\n", indent)),
+				Structured::Continue => out.push_str(&format!("{}continue;\n", indent)),
+			}
+		}
+	}
+
+	/* The body split at its labels, every piece an arm of a match on where control is */
+	fn state_machine(&self, out: &mut String, body: &[Statement]) {
+		let (pieces, arms) = pieces(body);
+
+		out.push_str("\tlet mut pc = 0;\n\tloop {\n\t\tmatch pc {\n");
+		for (number, piece) in pieces.iter().enumerate() {
+			out.push_str(&format!("\t\t\t{} => {{\n", number));
+			for statement in piece {
+				let line = match **statement {
+					Statement::Goto(ref label) => format!("pc = {};\n\t\t\t\tcontinue;", arms[label.as_str()]),
+					Statement::Branch(ref condition, jump_if, ref label) => {
+						let test = if jump_if { self.condition(condition) } else { negated(&self.condition(condition)) };
+						format!("if {} {{\n\t\t\t\t\tpc = {};\n\t\t\t\t\tcontinue;\n\t\t\t\t}}", test, arms[label.as_str()])
+					}
+					ref statement => self.statement(statement),
+				};
+				out.push_str(&format!("\t\t\t\t{}\n", line));
+			}
+			/* Falling off the end of a piece goes on with the next one */
+			match piece.last() {
+				Some(&&Statement::Goto(_)) | Some(&&Statement::Return) | Some(&&Statement::Halt) => {}
+				_ => out.push_str(&format!("\t\t\t\tpc = {};\n", number + 1)),
+			}
+			out.push_str("\t\t\t}\n");
+		}
+		out.push_str("\t\t\t_ => unreachable!(),\n\t\t}\n\t}\n");
+	}
+
+	fn statement(&self, statement: &Statement) -> String {
+		match *statement {
+			Statement::Assign(ref variable, ref value) => format!("{} = {};", self.variable(variable), self.value(value)),
+			Statement::Spill(slot, ref value) => format!("{} = {};", self.names.slot(slot), self.value(value)),
+			Statement::Evaluate(ref value) => format!("let _ = {};", self.value(value)),
+			Statement::Print(ref value) => format!("println!(\"{{}}\", {});", self.value(value)),
+			Statement::Show(ref text) => format!("println!(\"{{}}\", {});", string_literal(text)),
+			Statement::Call(ref label, ref arguments) => {
+				let arguments: Vec<String> = arguments.iter().map(|argument| match *argument {
+					Argument::Value(ref variable) => self.variable(variable),
+					Argument::Reference(ref variable) => self.reference(variable),
+				}).collect();
+				format!("{}({});", self.names.function(label), arguments.join(", "))
+			}
+			Statement::Return => "return;".to_string(),
+			Statement::Halt if self.function.label.is_none() => "return;".to_string(),
+			Statement::Halt => "std::process::exit(0);".to_string(),
+			/* Only the state machine has labels and jumps, and writes them itself */
+			Statement::Label(_) | Statement::Goto(_) | Statement::Branch(..) => unreachable!(),
+		}
+	}
+
+	fn variable(&self, variable: &Variable) -> String {
+		let identifier = self.names.variable(self.function, variable);
+		if self.function.is_pointer(variable) {
+			format!("*{}", identifier)
+		} else {
+			identifier.to_string()
+		}
+	}
+
+	/* References a subroutine received are passed on as they are, which reborrows them */
+	fn reference(&self, variable: &Variable) -> String {
+		let identifier = self.names.variable(self.function, variable);
+		if self.function.is_pointer(variable) {
+			identifier.to_string()
+		} else {
+			format!("&mut {}", identifier)
+		}
+	}
+
+	/* An i64, either a single term or wrapped so that it can be an operand of anything */
+	fn value(&self, value: &Expr) -> String {
+		match *value {
+			Expr::Constant(i64::MIN) => "i64::MIN".to_string(),
+			/* Where nothing else fixes the type, Rust takes a literal to be an i32 */
+			Expr::Constant(constant) if constant < i64::from(i32::MIN) || constant > i64::from(i32::MAX) => format!("{}i64", constant),
+			Expr::Constant(constant) => constant.to_string(),
+			Expr::Variable(ref variable) => self.variable(variable),
+			Expr::Slot(slot) => self.names.slot(slot),
+			Expr::Binary(op, ref left, ref right) => match arithmetic(op) {
+				Some(function) => format!("{}({}, {})", function, self.value(left), self.value(right)),
+				None => format!("({} as i64)", parenthesized(&self.condition(value))),
+			},
+			Expr::Not(_) => format!("({} as i64)", parenthesized(&self.condition(value))),
+		}
+	}
+
+	/* A bool. Both sides of & and | are evaluated, as the stack machine does */
+	fn condition(&self, value: &Expr) -> String {
+		match *value {
+			Expr::Binary(op, ref left, ref right) => match (logic(op), comparison(op)) {
+				(Some(symbol), _) => {
+					format!("{} {} {}", parenthesized(&self.condition(left)), symbol, parenthesized(&self.condition(right)))
+				}
+				(_, Some(symbol)) => format!("{} {} {}", self.value(left), symbol, self.value(right)),
+				_ => format!("{} != 0", self.value(value)),
+			},
+			Expr::Not(ref operand) => negated(&self.condition(operand)),
+			_ => format!("{} != 0", self.value(value)),
+		}
+	}
+}
+
+/* Arithmetic as functions, which wrap around on overflow like the interpreter */
+fn arithmetic(op: BinaryOp) -> Option<&'static str> {
+	match op {
+		BinaryOp::Add => Some("i64::wrapping_add"),
+		BinaryOp::Sub => Some("i64::wrapping_sub"),
+		BinaryOp::Mul => Some("i64::wrapping_mul"),
+		BinaryOp::Div => Some("jaz_div"),
+		BinaryOp::Rem => Some("jaz_rem"),
+		_ => None,
+	}
+}
+
+fn logic(op: BinaryOp) -> Option<&'static str> {
+	match op {
+		BinaryOp::And => Some("&"),
+		BinaryOp::Or => Some("|"),
+		_ => None,
+	}
+}
+
+fn comparison(op: BinaryOp) -> Option<&'static str> {
+	match op {
+		BinaryOp::NotEqual => Some("!="),
+		BinaryOp::LessEqual => Some("<="),
+		BinaryOp::GreaterEqual => Some(">="),
+		BinaryOp::Less => Some("<"),
+		BinaryOp::Greater => Some(">"),
+		BinaryOp::Equal => Some("=="),
+		_ => None,
+	}
+}
+
+/* Conditions are single terms or contain a space */
+fn parenthesized(condition: &str) -> String {
+	if condition.contains(' ') {
+		format!("({})", condition)
+	} else {
+		condition.to_string()
+	}
+}
+
+fn negated(condition: &str) -> String {
+	format!("!{}", parenthesized(condition))
+}
+
+fn string_literal(text: &str) -> String {
+	let mut literal = String::from("\"");
+	for c in text.chars() {
+		match c {
+			'"' => literal.push_str("\\\""),
+			'\\' => literal.push_str("\\\\"),
+			'\t' => literal.push_str("\\t"),
+			'\n' => literal.push_str("\\n"),
+			_ => literal.push(c),
+		}
+	}
+	literal.push('"');
+	literal
+}
@@ -0,0 +1,364 @@
+/* Control Flow Structuring
+ * Recovers loops and conditionals from the flat statements of a function,
+ * whose labels and jumps are the edges of its control flow graph. A label
+ * that a later jump goes back to heads a loop, as long as nothing outside the
+ * loop jumps into its body. A conditional jump forward over statements that
+ * nothing else jumps into is an if, with an else when those statements end by
+ * jumping over the ones that follow. Jumps out of the innermost loop become
+ * break and continue. Whatever fits none of these, which is always the case
+ * for irreducible flow, stays a goto to a label that is kept for it.
+ */
+use std::collections::{HashMap, HashSet};
+
+use codegen::{BinaryOp, Expr, Statement};
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum Structured {
+	Simple(Statement),								/* Never a Branch, Label and Goto only where structuring gave up */
+	If(Expr, Vec<Structured>, Vec<Structured>),		/* Condition, then, else */
+	While(Expr, Vec<Structured>),
+	Loop(Vec<Structured>),							/* Repeats until a break, halt or return */
+	Break,
+	Continue,
+}
+
+/* Where control goes when a region runs off its end, and what break and continue mean in it */
+#[derive(Clone, Copy)]
+struct Context {
+	follow: usize,			/* Position of the statement control falls through to */
+	exit: Option<usize>,	/* Position after the innermost loop */
+	repeat: Option<usize>,	/* Position of the innermost loop's header */
+}
+
+struct Structurer<'a> {
+	body: &'a [Statement],
+	labels: HashMap<&'a str, usize>,		/* Position of every label */
+	sources: HashMap<&'a str, Vec<usize>>,	/* Positions of the jumps to every label */
+}
+
+pub fn structure(body: &[Statement]) -> Vec<Structured> {
+	let mut structurer = Structurer { body, labels: HashMap::new(), sources: HashMap::new() };
+	for (position, statement) in body.iter().enumerate() {
+		match *statement {
+			Statement::Label(ref label) => {
+				structurer.labels.insert(label, position);
+			}
+			Statement::Goto(ref label) | Statement::Branch(_, _, ref label) => {
+				structurer.sources.entry(label).or_default().push(position);
+			}
+			_ => {}
+		}
+	}
+
+	let structured = structurer.region(0, body.len(), Context { follow: body.len(), exit: None, repeat: None });
+	let mut kept: HashSet<String> = HashSet::new();
+	gotos(&structured, &mut kept);
+	prune(structured, &kept)
+}
+
+impl<'a> Structurer<'a> {
+	/* Whether jumping to `label` ends up at `position`, with nothing but labels in between */
+	fn lands(&self, label: &str, position: usize) -> bool {
+		match self.labels.get(label) {
+			Some(&at) => at >= position && self.body[position..at].iter().all(is_label),
+			None => false,
+		}
+	}
+
+	/* Whether the only jumps to labels in start..end come from inside it */
+	fn closed(&self, start: usize, end: usize) -> bool {
+		self.body[start..end].iter().all(|statement| match *statement {
+			Statement::Label(ref label) => self.sources.get(label.as_str()).is_none_or(|sources| {
+				sources.iter().all(|&source| start <= source && source < end)
+			}),
+			_ => true,
+		})
+	}
+
+	fn region(&self, start: usize, end: usize, context: Context) -> Vec<Structured> {
+		let mut out: Vec<Structured> = Vec::new();
+		let mut position = start;
+		while position < end {
+			let at_end = self.body[position + 1..end].iter().all(is_label);
+			match self.body[position] {
+				Statement::Label(ref label) => {
+					out.push(Structured::Simple(self.body[position].clone()));
+					/* The last jump back to the label closes the loop it heads */
+					let back = self.sources.get(label.as_str())
+						.and_then(|sources| sources.iter().cloned().filter(|&source| position < source && source < end).max());
+					if let Some(back) = back {
+						if self.closed(position + 1, back + 1) {
+							out.push(self.looped(position, back));
+							position = back + 1;
+							continue;
+						}
+					}
+				}
+				/* Jumps to where control goes anyway, which code left out as unreachable can leave behind */
+				Statement::Goto(ref label) => {
+					if !(self.lands(label, position + 1) || at_end && self.lands(label, context.follow)) {
+						out.push(self.jump(label, context));
+					}
+				}
+				Statement::Branch(ref condition, jump_if, ref label) => {
+					position = self.branch(&mut out, position, end, condition, jump_if, label, context);
+					continue;
+				}
+				ref statement => out.push(Structured::Simple(statement.clone())),
+			}
+			position += 1;
+		}
+		out
+	}
+
+	/* An unconditional jump, as break, continue or goto */
+	fn jump(&self, label: &str, context: Context) -> Structured {
+		if context.exit.is_some_and(|exit| self.lands(label, exit)) {
+			Structured::Break
+		} else if context.repeat.is_some_and(|repeat| self.lands(label, repeat)) {
+			Structured::Continue
+		} else {
+			Structured::Simple(Statement::Goto(label.to_string()))
+		}
+	}
+
+	/* Structures the conditional jump at `position`, returns where to carry on */
+	#[allow(clippy::too_many_arguments)]
+	fn branch(
+		&self,
+		out: &mut Vec<Structured>,
+		position: usize,
+		end: usize,
+		condition: &Expr,
+		jump_if: bool,
+		label: &str,
+		context: Context
+	) -> usize {
+		let jumps = if jump_if { condition.clone() } else { negate(condition) };
+		let stays = if jump_if { negate(condition) } else { condition.clone() };
+
+		let target = self.labels.get(label).cloned().unwrap_or(0);
+		let exits = context.exit.is_some_and(|exit| self.lands(label, exit));
+		let repeats = context.repeat.is_some_and(|repeat| self.lands(label, repeat));
+		if exits || repeats || target <= position || target > end || !self.closed(position + 1, target) {
+			out.push(Structured::If(jumps, vec![self.jump(label, context)], Vec::new()));
+			return position + 1;
+		}
+
+		/* The then part jumps over an else part that only this branch enters */
+		if target > position + 1 {
+			if let Statement::Goto(ref over) = self.body[target - 1] {
+				let join = self.labels.get(over.as_str()).cloned().unwrap_or(0);
+				let only_here = self.sources[label] == [position];
+				if join > target && join <= end && only_here && self.closed(target + 1, join) {
+					let inner = Context { follow: join, ..context };
+					let then = self.region(position + 1, target - 1, inner);
+					let otherwise = self.region(target + 1, join, inner);
+					out.push(Structured::If(stays, then, otherwise));
+					return join;
+				}
+			}
+		}
+
+		let then = self.region(position + 1, target, Context { follow: target, ..context });
+		if !then.is_empty() {
+			out.push(Structured::If(stays, then, Vec::new()));
+		} else if stays.divides() {
+			out.push(Structured::Simple(Statement::Evaluate(stays)));
+		}
+		target
+	}
+
+	/* The loop headed by the label at `header` and closed by the jump at `back` */
+	fn looped(&self, header: usize, back: usize) -> Structured {
+		let context = Context { follow: header, exit: Some(back + 1), repeat: Some(header) };
+		let mut body = match self.body[back] {
+			/* A conditional jump back leaves the loop when it is not taken */
+			Statement::Branch(ref condition, jump_if, _) => {
+				let stays = if jump_if { negate(condition) } else { condition.clone() };
+				let mut body = self.region(header + 1, back, Context { follow: back, ..context });
+				body.push(Structured::If(stays, vec![Structured::Break], Vec::new()));
+				body
+			}
+			_ => self.region(header + 1, back, context),
+		};
+
+		/* A test that leaves the loop before anything else happens is a while condition */
+		let condition = match body.first() {
+			Some(Structured::If(condition, then, otherwise)) if *then == [Structured::Break] && otherwise.is_empty() => Some(negate(condition)),
+			_ => None,
+		};
+		match condition {
+			Some(condition) => {
+				body.remove(0);
+				Structured::While(condition, body)
+			}
+			None => Structured::Loop(body),
+		}
+	}
+}
+
+fn is_label(statement: &Statement) -> bool {
+	matches!(*statement, Statement::Label(_))
+}
+
+/* The opposite condition, without stacking up `!` */
+pub fn negate(condition: &Expr) -> Expr {
+	let flipped = |op| match op {
+		BinaryOp::Equal => Some(BinaryOp::NotEqual),
+		BinaryOp::NotEqual => Some(BinaryOp::Equal),
+		BinaryOp::Less => Some(BinaryOp::GreaterEqual),
+		BinaryOp::GreaterEqual => Some(BinaryOp::Less),
+		BinaryOp::Greater => Some(BinaryOp::LessEqual),
+		BinaryOp::LessEqual => Some(BinaryOp::Greater),
+		_ => None,
+	};
+	match *condition {
+		Expr::Not(ref operand) => (**operand).clone(),
+		Expr::Binary(op, ref left, ref right) if flipped(op).is_some() => {
+			Expr::Binary(flipped(op).unwrap(), left.clone(), right.clone())
+		}
+		_ => Expr::Not(Box::new(condition.clone())),
+	}
+}
+
+/* Labels the remaining gotos still need */
+fn gotos(structured: &[Structured], kept: &mut HashSet<String>) {
+	for item in structured {
+		match *item {
+			Structured::Simple(Statement::Goto(ref label)) => {
+				kept.insert(label.clone());
+			}
+			Structured::If(_, ref then, ref otherwise) => {
+				gotos(then, kept);
+				gotos(otherwise, kept);
+			}
+			Structured::While(_, ref body) | Structured::Loop(ref body) => gotos(body, kept),
+			_ => {}
+		}
+	}
+}
+
+/* Whether structuring left a goto behind, which a language without goto cannot write */
+pub fn gives_up(items: &[Structured]) -> bool {
+	items.iter().any(|item| match *item {
+		Structured::Simple(Statement::Goto(_)) => true,
+		Structured::If(_, ref then, ref otherwise) => gives_up(then) || gives_up(otherwise),
+		Structured::While(_, ref body) | Structured::Loop(ref body) => gives_up(body),
+		_ => false,
+	})
+}
+
+/* The body split at its labels, for a state machine to run where structuring
+ * gave up: the statements of every piece, and the piece each label starts.
+ */
+pub fn pieces(body: &[Statement]) -> (Vec<Vec<&Statement>>, HashMap<&str, usize>) {
+	let mut pieces: Vec<Vec<&Statement>> = vec![Vec::new()];
+	let mut starts: HashMap<&str, usize> = HashMap::new();
+	for statement in body {
+		if let Statement::Label(ref label) = *statement {
+			/* Labels in a row start the same piece */
+			if !pieces.last().unwrap().is_empty() {
+				pieces.push(Vec::new());
+			}
+			starts.insert(label, pieces.len() - 1);
+		} else {
+			pieces.last_mut().unwrap().push(statement);
+		}
+	}
+	(pieces, starts)
+}
+
+fn prune(structured: Vec<Structured>, kept: &HashSet<String>) -> Vec<Structured> {
+	structured.into_iter().filter_map(|item| match item {
+		Structured::Simple(Statement::Label(ref label)) if !kept.contains(label) => None,
+		Structured::If(condition, then, otherwise) => Some(Structured::If(condition, prune(then, kept), prune(otherwise, kept))),
+		Structured::While(condition, body) => Some(Structured::While(condition, prune(body, kept))),
+		Structured::Loop(body) => Some(Structured::Loop(prune(body, kept))),
+		item => Some(item),
+	}).collect()
+}
+
+#[cfg(test)]
+mod tests {
+	use super::{gives_up, pieces, structure, Structured};
+	use codegen::{lower, Statement};
+
+	/* The main function's statements after lowering */
+	fn body(source: &str) -> Vec<Statement> {
+		let mut module = lower(&::parse(source).unwrap()).unwrap();
+		module.functions.remove(0).body
+	}
+
+	/* A compact picture of the structure: statements by kind, blocks in brackets */
+	fn shape(items: &[Structured]) -> String {
+		let words: Vec<String> = items.iter().map(|item| match *item {
+			Structured::Simple(Statement::Assign(..)) => "assign".to_string(),
+			Structured::Simple(Statement::Show(ref text)) => format!("show {}", text),
+			Structured::Simple(Statement::Label(ref label)) => format!("label {}", label),
+			Structured::Simple(Statement::Goto(ref label)) => format!("goto {}", label),
+			Structured::Simple(Statement::Halt) => "halt".to_string(),
+			Structured::Simple(ref statement) => format!("{:?}", statement),
+			Structured::If(_, ref then, ref otherwise) if otherwise.is_empty() => format!("if [{}]", shape(then)),
+			Structured::If(_, ref then, ref otherwise) => format!("if [{}] else [{}]", shape(then), shape(otherwise)),
+			Structured::While(_, ref body) => format!("while [{}]", shape(body)),
+			Structured::Loop(ref body) => format!("loop [{}]", shape(body)),
+			Structured::Break => "break".to_string(),
+			Structured::Continue => "continue".to_string(),
+		}).collect();
+		words.join(", ")
+	}
+
+	fn structured(source: &str) -> String {
+		shape(&structure(&body(source)))
+	}
+
+	#[test]
+	fn finds_while_loops() {
+		let source = "lvalue i\npush 3\n:=\nlabel top\nrvalue i\ngofalse out\nlvalue i\nrvalue i\npush 1\n-\n:=\ngoto top\nlabel out\nhalt\n";
+		assert_eq!(structured(source), "assign, while [assign], halt");
+	}
+
+	#[test]
+	fn finds_if_and_else() {
+		let source = "rvalue x\ngofalse else\nshow then\ngoto done\nlabel else\nshow else\nlabel done\nhalt\n";
+		assert_eq!(structured(source), "if [show then] else [show else], halt");
+		let source = "rvalue x\ngofalse skip\nshow then\nlabel skip\nhalt\n";
+		assert_eq!(structured(source), "if [show then], halt");
+	}
+
+	#[test]
+	fn turns_jumps_out_of_loops_into_break_and_continue() {
+		let source = "label top\nrvalue x\ngotrue out\nrvalue y\ngotrue top\nshow body\ngoto top\nlabel out\nhalt\n";
+		assert_eq!(structured(source), "while [if [continue], show body], halt");
+		let source = "label top\nshow first\nrvalue x\ngotrue out\nshow second\ngoto top\nlabel out\nhalt\n";
+		assert_eq!(structured(source), "loop [show first, if [break], show second], halt");
+	}
+
+	#[test]
+	fn keeps_gotos_for_irreducible_flow() {
+		/* Both a and b can be entered first, so neither heads a loop */
+		let source = "rvalue x\ngotrue b\nlabel a\nshow a\nrvalue y\ngotrue b\nhalt\nlabel b\nshow b\nrvalue z\ngotrue a\nhalt\n";
+		let items = structure(&body(source));
+		let picture = shape(&items);
+		assert!(gives_up(&items), "{}", picture);
+		assert!(picture.contains("label a") && picture.contains("label b") && picture.contains("goto b"), "{}", picture);
+
+		/* Labels nothing jumps to any more are pruned */
+		let items = structure(&body("label top\nrvalue x\ngofalse out\nshow x\ngoto top\nlabel out\nhalt\n"));
+		assert!(!gives_up(&items));
+		assert!(!shape(&items).contains("label"));
+	}
+
+	#[test]
+	fn splits_pieces_at_labels() {
+		let label = |name: &str| Statement::Label(name.to_string());
+		let show = |text: &str| Statement::Show(text.to_string());
+		let body = vec![show("start"), label("a"), label("c"), show("a"), Statement::Halt, label("b"), show("b"), Statement::Goto("a".to_string())];
+		let (pieces, starts) = pieces(&body);
+		assert_eq!(pieces.len(), 3);
+		assert_eq!((starts["a"], starts["c"], starts["b"]), (1, 1, 2));
+		assert_eq!(pieces.iter().map(Vec::len).collect::<Vec<usize>>(), vec![1, 2, 2]);
+		assert!(pieces.iter().flatten().all(|statement| !matches!(**statement, Statement::Label(_))));
+	}
+}
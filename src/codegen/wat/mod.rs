@@ -0,0 +1,458 @@
+/* WebAssembly Backend
+ * Writes a module in the WebAssembly text format. The program entry's own
+ * frame becomes mutable globals and everything else i64 locals. WebAssembly
+ * cannot point into another function's locals, so the variables a subroutine
+ * hands back are copied in as parameters and copied out as its results, which
+ * the caller stores again after the call. Output goes through functions the
+ * host provides, with the text of `show` in a data segment:
+ *
+ *	jaz.print(value: i64)				write the value and a newline
+ *	jaz.show(offset: i32, length: i32)	write UTF-8 text from memory and a newline
+ *	jaz.fail(offset: i32, length: i32)	write the text to stderr, exit with RUNTIME_ERROR_STATUS
+ *
+ * Loops and conditionals come from codegen::structure. A function whose
+ * jumps do not all fit them runs as a state machine, a br_table picking the
+ * piece of the body between two labels. A halt inside a subroutine sets a
+ * global that every caller checks after the call, and returns. The result is
+ * checked with validate before it is handed out.
+ */
+pub mod validate;
+
+use std::collections::BTreeMap;
+
+use codegen::structure::{gives_up, pieces, structure, Structured};
+use codegen::{lower, Argument, BinaryOp, CodegenError, Expr, Frame, Function, Mode, Module, Names, Parameter, Statement, Variable};
+use codegen::DIVISION_BY_ZERO;
+use parsetree::Program;
+
+/* Names of the imports, helpers and the entry point, plus the state machine's locals and the halt flag */
+const RESERVED: &[&str] = &["print", "show", "fail", "jaz_div", "jaz_rem", "main", "pc", "halted"];
+
+pub fn generate(program: &Program) -> Result<String, CodegenError> {
+	let module = lower(program)?;
+	let names = Names::new(&module, RESERVED);
+	let statements = || module.functions.iter().flat_map(|function| &function.body);
+
+	/* Every distinct text goes into the data segment once */
+	let mut texts: BTreeMap<&str, (usize, usize)> = BTreeMap::new();
+	let mut data: Vec<u8> = Vec::new();
+	let dividing = module.applies(BinaryOp::Div) || module.applies(BinaryOp::Rem);
+	let shown = statements().filter_map(|statement| match *statement {
+		Statement::Show(ref text) => Some(text.as_str()),
+		_ => None,
+	});
+	for text in shown.chain(if dividing { Some(DIVISION_BY_ZERO) } else { None }) {
+		if !texts.contains_key(text) {
+			texts.insert(text, (data.len(), text.len()));
+			data.extend_from_slice(text.as_bytes());
+		}
+	}
+
+	let halting = module.functions.iter()
+		.any(|function| function.label.is_some() && function.body.contains(&Statement::Halt));
+
+	let mut out = String::from("(module\n");
+	if statements().any(|statement| matches!(*statement, Statement::Print(_))) {
+		out.push_str("\t(import \"jaz\" \"print\" (func $print (param i64)))\n");
+	}
+	if statements().any(|statement| matches!(*statement, Statement::Show(_))) {
+		out.push_str("\t(import \"jaz\" \"show\" (func $show (param i32 i32)))\n");
+	}
+	if dividing {
+		out.push_str("\t(import \"jaz\" \"fail\" (func $fail (param i32 i32)))\n");
+	}
+	out.push_str(&format!("\t(memory (export \"memory\") {})\n", data.len().div_ceil(65536).max(1)));
+	if !data.is_empty() {
+		out.push_str(&format!("\t(data (i32.const 0) {})\n", string_literal(&data)));
+	}
+	let main = &module.functions[0];
+	for variable in &module.variables {
+		out.push_str(&format!("\t(global {} (mut i64) (i64.const 0))\n", identifier(names.variable(main, &Variable::new(Frame::Own, variable)))));
+	}
+	if halting {
+		out.push_str("\t(global $halted (mut i32) (i32.const 0))\n");
+	}
+	if dividing {
+		let (offset, length) = texts[DIVISION_BY_ZERO];
+		write_division(&mut out, &module, offset, length);
+	}
+
+	for function in &module.functions {
+		let context = Context { module: &module, function, names: &names, texts: &texts, halting };
+		context.function(&mut out);
+	}
+	out.push_str(")\n");
+
+	match validate::validate(&out) {
+		Ok(()) => Ok(out),
+		Err(why) => Err(CodegenError::InvalidOutput(why.to_string())),
+	}
+}
+
+/* Division helpers, see codegen::DIVISION_BY_ZERO. i64.div_s traps where the
+ * interpreter wraps, so the most negative value divided by -1 is handled first.
+ */
+fn write_division(out: &mut String, module: &Module, offset: usize, length: usize) {
+	for &(op, name, instruction, by_minus_one) in &[
+		(BinaryOp::Div, "jaz_div", "i64.div_s", "i64.const 0\n\t\t\tlocal.get $a\n\t\t\ti64.sub"),
+		(BinaryOp::Rem, "jaz_rem", "i64.rem_s", "i64.const 0"),
+	] {
+		if module.applies(op) {
+			out.push_str(&format!("\t(func ${} (param $a i64) (param $b i64) (result i64)\n", name));
+			out.push_str(&format!(
+				"\t\tlocal.get $b\n\t\ti64.eqz\n\t\tif\n\t\t\ti32.const {}\n\t\t\ti32.const {}\n\t\t\tcall $fail\n\t\t\tunreachable\n\t\tend\n",
+				offset, length
+			));
+			out.push_str(&format!("\t\tlocal.get $b\n\t\ti64.const -1\n\t\ti64.eq\n\t\tif\n\t\t\t{}\n\t\t\treturn\n\t\tend\n", by_minus_one));
+			out.push_str(&format!("\t\tlocal.get $a\n\t\tlocal.get $b\n\t\t{}\n\t)\n", instruction));
+		}
+	}
+}
+
+/* The function being written, and what the whole module provides it with */
+struct Context<'a> {
+	module: &'a Module,
+	function: &'a Function,
+	names: &'a Names,
+	texts: &'a BTreeMap<&'a str, (usize, usize)>,
+	halting: bool,		/* Whether a subroutine can halt, so calls have to look */
+}
+
+impl<'a> Context<'a> {
+	fn function(&self, out: &mut String) {
+		let function = self.function;
+		let own = |parameter: &Parameter| self.names.variable(function, &Variable::new(Frame::Own, &parameter.name));
+
+		let mut header = match function.label {
+			Some(ref label) => format!("\t(func {}", identifier(self.names.function(label))),
+			None => "\t(func $main (export \"main\")".to_string(),
+		};
+		let mut locals: Vec<(String, &str)> = Vec::new();
+		for parameter in &function.parameters {
+			match parameter.mode {
+				/* An Out parameter is copied in too: a path that never writes it must hand back the caller's value */
+				Mode::In | Mode::Out | Mode::InOut => header.push_str(&format!(" (param {} i64)", identifier(own(parameter)))),
+				Mode::Local => locals.push((own(parameter).to_string(), "i64")),
+			}
+		}
+		if self.results() > 0 {
+			header.push_str(&format!(" (result{})", " i64".repeat(self.results())));
+		}
+		out.push_str(&header);
+		out.push('\n');
+
+		locals.extend(function.locals.iter().map(|local| (self.names.variable(function, local).to_string(), "i64")));
+		locals.extend((0..function.slots).map(|slot| (self.names.slot(slot), "i64")));
+		let structured = structure(&function.body);
+		let gives_up = gives_up(&structured);
+		if gives_up {
+			locals.push(("pc".to_string(), "i32"));
+		}
+		for (name, kind) in locals {
+			out.push_str(&format!("\t\t(local {} {})\n", identifier(&name), kind));
+		}
+
+		let mut body = String::new();
+		if gives_up {
+			self.state_machine(&mut body, &function.body);
+		} else {
+			self.block(&mut body, &structured, 2, &mut Vec::new(), &mut 0);
+		}
+		/* Control never runs off the end of the body, but the validator cannot tell after a loop */
+		if self.results() > 0 && !body.ends_with("return\n") {
+			body.push_str("\t\tunreachable\n");
+		}
+		out.push_str(&body);
+		out.push_str("\t)\n");
+	}
+
+	/* Out and InOut parameters come back as results, in order */
+	fn results(&self) -> usize {
+		self.function.parameters.iter().filter(|parameter| parameter.mode.by_pointer()).count()
+	}
+
+	/* `loops` holds the numbers of the enclosing loops, innermost last */
+	fn block(&self, out: &mut String, items: &[Structured], depth: usize, loops: &mut Vec<usize>, count: &mut usize) {
+		for item in items {
+			match *item {
+				Structured::Simple(ref statement) => self.statement(out, statement, depth),
+				Structured::If(ref condition, ref then, ref otherwise) => {
+					self.condition(out, condition, depth);
+					line(out, depth, "if");
+					self.block(out, then, depth + 1, loops, count);
+					if !otherwise.is_empty() {
+						line(out, depth, "else");
+						self.block(out, otherwise, depth + 1, loops, count);
+					}
+					line(out, depth, "end");
+				}
+				Structured::While(_, ref body) | Structured::Loop(ref body) => {
+					*count += 1;
+					let number = *count;
+					line(out, depth, &format!("block $break_{}", number));
+					line(out, depth + 1, &format!("loop $continue_{}", number));
+					if let Structured::While(ref condition, _) = *item {
+						self.condition(out, condition, depth + 2);
+						line(out, depth + 2, "i32.eqz");
+						line(out, depth + 2, &format!("br_if $break_{}", number));
+					}
+					loops.push(number);
+					self.block(out, body, depth + 2, loops, count);
+					loops.pop();
+					line(out, depth + 2, &format!("br $continue_{}", number));
+					line(out, depth + 1, "end");
+					line(out, depth, "end");
+				}
+				Structured::Break => line(out, depth, &format!("br $break_{}", loops.last().unwrap())),
+				Structured::Continue => line(out, depth, &format!("br $continue_{}", loops.last().unwrap())),
+			}
+		}
+	}
+
+	/* The body split at its labels. The blocks are nested so that leaving the
+	 * one for a piece lands at its start, and falling off a piece enters the next.
+	 */
+	fn state_machine(&self, out: &mut String, body: &[Statement]) {
+		let (pieces, arms) = pieces(body);
+
+		line(out, 2, "loop $dispatch");
+		for number in (0..pieces.len()).rev() {
+			line(out, 3, &format!("block $piece_{}", number));
+		}
+		line(out, 3, "local.get $pc");
+		let targets: Vec<String> = (0..pieces.len()).map(|number| format!("$piece_{}", number)).collect();
+		line(out, 3, &format!("br_table {} $piece_0", targets.join(" ")));
+		for piece in &pieces {
+			line(out, 3, "end");
+			for statement in piece {
+				match **statement {
+					Statement::Goto(ref label) => self.jump(out, arms[label.as_str()], 3),
+					Statement::Branch(ref condition, jump_if, ref label) => {
+						self.condition(out, condition, 3);
+						if !jump_if {
+							line(out, 3, "i32.eqz");
+						}
+						line(out, 3, "if");
+						self.jump(out, arms[label.as_str()], 4);
+						line(out, 3, "end");
+					}
+					ref statement => self.statement(out, statement, 3),
+				}
+			}
+		}
+		line(out, 2, "end");
+	}
+
+	fn jump(&self, out: &mut String, piece: usize, depth: usize) {
+		line(out, depth, &format!("i32.const {}", piece));
+		line(out, depth, "local.set $pc");
+		line(out, depth, "br $dispatch");
+	}
+
+	fn statement(&self, out: &mut String, statement: &Statement, depth: usize) {
+		match *statement {
+			Statement::Assign(ref variable, ref value) => {
+				self.value(out, value, depth);
+				line(out, depth, &self.set(variable));
+			}
+			Statement::Spill(slot, ref value) => {
+				self.value(out, value, depth);
+				line(out, depth, &format!("local.set {}", identifier(&self.names.slot(slot))));
+			}
+			Statement::Evaluate(ref value) => {
+				self.value(out, value, depth);
+				line(out, depth, "drop");
+			}
+			Statement::Print(ref value) => {
+				self.value(out, value, depth);
+				line(out, depth, "call $print");
+			}
+			Statement::Show(ref text) => {
+				let (offset, length) = self.texts[text.as_str()];
+				line(out, depth, &format!("i32.const {}", offset));
+				line(out, depth, &format!("i32.const {}", length));
+				line(out, depth, "call $show");
+			}
+			Statement::Call(ref label, ref arguments) => {
+				/* Values go in for every parameter, results come back for those the callee may write */
+				let callee = self.module.functions.iter().find(|function| function.label.as_ref() == Some(label)).unwrap();
+				let parameters = callee.parameters.iter().filter(|parameter| parameter.mode != Mode::Local);
+				let mut outputs: Vec<&Variable> = Vec::new();
+				for (parameter, argument) in parameters.zip(arguments) {
+					let variable = match *argument {
+						Argument::Value(ref variable) | Argument::Reference(ref variable) => variable,
+					};
+					line(out, depth, &self.get(variable));
+					if parameter.mode.by_pointer() {
+						outputs.push(variable);
+					}
+				}
+				line(out, depth, &format!("call {}", identifier(self.names.function(label))));
+				for variable in outputs.iter().rev() {
+					line(out, depth, &self.set(variable));
+				}
+				if self.halting {
+					line(out, depth, "global.get $halted");
+					line(out, depth, "if");
+					self.leave(out, depth + 1);
+					line(out, depth, "end");
+				}
+			}
+			Statement::Return => self.leave(out, depth),
+			Statement::Halt => {
+				if self.function.label.is_some() {
+					line(out, depth, "i32.const 1");
+					line(out, depth, "global.set $halted");
+				}
+				self.leave(out, depth);
+			}
+			/* Only the state machine has labels and jumps, and writes them itself */
+			Statement::Label(_) | Statement::Goto(_) | Statement::Branch(..) => unreachable!(),
+		}
+	}
+
+	/* Returns with the variables the subroutine hands back */
+	fn leave(&self, out: &mut String, depth: usize) {
+		for parameter in self.function.parameters.iter().filter(|parameter| parameter.mode.by_pointer()) {
+			line(out, depth, &self.get(&Variable::new(Frame::Own, &parameter.name)));
+		}
+		line(out, depth, "return");
+	}
+
+	/* The program entry's own frame is global, everything else local */
+	fn is_global(&self, variable: &Variable) -> bool {
+		self.function.label.is_none() && variable.frame == Frame::Own
+	}
+
+	fn get(&self, variable: &Variable) -> String {
+		let scope = if self.is_global(variable) { "global" } else { "local" };
+		format!("{}.get {}", scope, identifier(self.names.variable(self.function, variable)))
+	}
+
+	fn set(&self, variable: &Variable) -> String {
+		let scope = if self.is_global(variable) { "global" } else { "local" };
+		format!("{}.set {}", scope, identifier(self.names.variable(self.function, variable)))
+	}
+
+	/* Pushes an i64 */
+	fn value(&self, out: &mut String, value: &Expr, depth: usize) {
+		match *value {
+			Expr::Constant(constant) => line(out, depth, &format!("i64.const {}", constant)),
+			Expr::Variable(ref variable) => line(out, depth, &self.get(variable)),
+			Expr::Slot(slot) => line(out, depth, &format!("local.get {}", identifier(&self.names.slot(slot)))),
+			Expr::Binary(op, ref left, ref right) if !is_condition(value) => {
+				self.value(out, left, depth);
+				self.value(out, right, depth);
+				line(out, depth, arithmetic(op).unwrap());
+			}
+			Expr::Binary(..) | Expr::Not(_) => {
+				self.condition(out, value, depth);
+				line(out, depth, "i64.extend_i32_u");
+			}
+		}
+	}
+
+	/* Pushes an i32 that is 0 or 1. Both sides of And and Or are evaluated, as the stack machine does */
+	fn condition(&self, out: &mut String, value: &Expr, depth: usize) {
+		match *value {
+			Expr::Binary(op, ref left, ref right) if logic(op).is_some() => {
+				self.condition(out, left, depth);
+				self.condition(out, right, depth);
+				line(out, depth, logic(op).unwrap());
+			}
+			Expr::Binary(op, ref left, ref right) if comparison(op).is_some() => {
+				self.value(out, left, depth);
+				self.value(out, right, depth);
+				line(out, depth, comparison(op).unwrap());
+			}
+			Expr::Not(ref operand) if is_condition(operand) => {
+				self.condition(out, operand, depth);
+				line(out, depth, "i32.eqz");
+			}
+			Expr::Not(ref operand) => {
+				self.value(out, operand, depth);
+				line(out, depth, "i64.eqz");
+			}
+			_ => {
+				self.value(out, value, depth);
+				line(out, depth, "i64.const 0");
+				line(out, depth, "i64.ne");
+			}
+		}
+	}
+}
+
+/* Whether a value is computed as a condition in the first place */
+fn is_condition(value: &Expr) -> bool {
+	match *value {
+		Expr::Binary(op, _, _) => arithmetic(op).is_none(),
+		Expr::Not(_) => true,
+		Expr::Constant(_) | Expr::Variable(_) | Expr::Slot(_) => false,
+	}
+}
+
+/* Addition, subtraction and multiplication wrap around like the interpreter's */
+fn arithmetic(op: BinaryOp) -> Option<&'static str> {
+	match op {
+		BinaryOp::Add => Some("i64.add"),
+		BinaryOp::Sub => Some("i64.sub"),
+		BinaryOp::Mul => Some("i64.mul"),
+		BinaryOp::Div => Some("call $jaz_div"),
+		BinaryOp::Rem => Some("call $jaz_rem"),
+		_ => None,
+	}
+}
+
+fn logic(op: BinaryOp) -> Option<&'static str> {
+	match op {
+		BinaryOp::And => Some("i32.and"),
+		BinaryOp::Or => Some("i32.or"),
+		_ => None,
+	}
+}
+
+fn comparison(op: BinaryOp) -> Option<&'static str> {
+	match op {
+		BinaryOp::NotEqual => Some("i64.ne"),
+		BinaryOp::LessEqual => Some("i64.le_s"),
+		BinaryOp::GreaterEqual => Some("i64.ge_s"),
+		BinaryOp::Less => Some("i64.lt_s"),
+		BinaryOp::Greater => Some("i64.gt_s"),
+		BinaryOp::Equal => Some("i64.eq"),
+		_ => None,
+	}
+}
+
+fn line(out: &mut String, depth: usize, text: &str) {
+	for _ in 0..depth {
+		out.push('\t');
+	}
+	out.push_str(text);
+	out.push('\n');
+}
+
+/* jaz names may hold any letter, identifiers only printable ASCII. `@` never occurs in a jaz name */
+fn identifier(name: &str) -> String {
+	let mut identifier = String::from("$");
+	for c in name.chars() {
+		if c.is_ascii_alphanumeric() || c == '_' {
+			identifier.push(c);
+		} else {
+			identifier.push_str(&format!("@{:x}", c as u32));
+		}
+	}
+	identifier
+}
+
+fn string_literal(bytes: &[u8]) -> String {
+	let mut literal = String::from("\"");
+	for &byte in bytes {
+		match byte {
+			b'"' | b'\\' => literal.push_str(&format!("\\{}", byte as char)),
+			b' '..=b'~' => literal.push(byte as char),
+			_ => literal.push_str(&format!("\\{:02x}", byte)),
+		}
+	}
+	literal.push('"');
+	literal
+}
@@ -0,0 +1,1002 @@
+/* WAT Validation
+ * Checks a module in the WebAssembly text format the way an engine checks one
+ * before it runs it: every name resolves, imports come first, data fits in
+ * memory, export names are unique, and the operand stack holds values of the
+ * right types at every instruction and at the end of every block. It reads
+ * the part of the text format the backend writes: import, memory, data,
+ * global, func and export fields, exports written inline, and instructions in
+ * the flat form rather than folded into S-expressions.
+ */
+use std::collections::HashMap;
+use std::fmt;
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct WatError {
+	pub line: usize,
+	pub message: String,
+}
+
+impl fmt::Display for WatError {
+	fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+		write!(f, "line {}: {}", self.line, self.message)
+	}
+}
+
+fn error<T, S: Into<String>>(line: usize, message: S) -> Result<T, WatError> {
+	Err(WatError { line, message: message.into() })
+}
+
+/* Checks the text of a whole module */
+pub fn validate(text: &str) -> Result<(), WatError> {
+	let tokens = tokenize(text)?;
+	let mut position = 0;
+	let module = parse(&tokens, &mut position)?;
+	if let Some(&(_, line)) = tokens.get(position) {
+		return error(line, "text after the end of the module");
+	}
+	let fields = match module {
+		Sexpr::List(ref items, _) if keyword(items) == Some("module") => items,
+		_ => return error(module.line(), "expected `(module ...)`"),
+	};
+	let mut cursor = 1;
+	take_id(fields, &mut cursor);
+	Module::check(&fields[cursor..])
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+enum Token {
+	Open,
+	Close,
+	Atom(String),
+	Text(Vec<u8>),
+}
+
+fn tokenize(text: &str) -> Result<Vec<(Token, usize)>, WatError> {
+	let mut tokens = Vec::new();
+	let mut chars = text.chars().peekable();
+	let mut line = 1;
+	while let Some(c) = chars.next() {
+		match c {
+			'\n' => line += 1,
+			c if c.is_whitespace() => {}
+			';' if chars.peek() == Some(&';') => {
+				while chars.peek().is_some_and(|&c| c != '\n') {
+					chars.next();
+				}
+			}
+			'(' if chars.peek() == Some(&';') => {
+				/* Block comments nest */
+				chars.next();
+				let (start, mut depth, mut previous) = (line, 1, ' ');
+				while depth > 0 {
+					let c = match chars.next() {
+						Some(c) => c,
+						None => return error(start, "unterminated block comment"),
+					};
+					/* A character closes or opens at most one comment */
+					previous = match (previous, c) {
+						('(', ';') => {
+							depth += 1;
+							' '
+						}
+						(';', ')') => {
+							depth -= 1;
+							' '
+						}
+						(_, '\n') => {
+							line += 1;
+							c
+						}
+						_ => c,
+					};
+				}
+			}
+			'(' => tokens.push((Token::Open, line)),
+			')' => tokens.push((Token::Close, line)),
+			'"' => {
+				let start = line;
+				let mut bytes: Vec<u8> = Vec::new();
+				loop {
+					match chars.next() {
+						None => return error(start, "unterminated string"),
+						Some('"') => break,
+						Some('\n') => return error(line, "line break in a string"),
+						Some('\\') => match chars.next() {
+							Some('n') => bytes.push(b'\n'),
+							Some('t') => bytes.push(b'\t'),
+							Some('r') => bytes.push(b'\r'),
+							Some(c @ ('"' | '\'' | '\\')) => bytes.push(c as u8),
+							Some(high) if high.is_ascii_hexdigit() => match chars.next().and_then(|low| low.to_digit(16)) {
+								Some(low) => bytes.push((high.to_digit(16).unwrap() * 16 + low) as u8),
+								None => return error(line, "expected two hexadecimal digits after `\\`"),
+							},
+							_ => return error(line, "unknown escape in a string"),
+						},
+						Some(c) => {
+							let mut buffer = [0; 4];
+							bytes.extend_from_slice(c.encode_utf8(&mut buffer).as_bytes());
+						}
+					}
+				}
+				tokens.push((Token::Text(bytes), start));
+			}
+			c => {
+				let mut atom = c.to_string();
+				while let Some(&c) = chars.peek() {
+					if c.is_whitespace() || c == '(' || c == ')' || c == '"' || c == ';' {
+						break;
+					}
+					atom.push(c);
+					chars.next();
+				}
+				tokens.push((Token::Atom(atom), line));
+			}
+		}
+	}
+	Ok(tokens)
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+enum Sexpr {
+	List(Vec<Sexpr>, usize),	/* Items, line of the opening parenthesis */
+	Atom(String, usize),
+	Text(Vec<u8>, usize),
+}
+
+impl Sexpr {
+	fn line(&self) -> usize {
+		match *self {
+			Sexpr::List(_, line) | Sexpr::Atom(_, line) | Sexpr::Text(_, line) => line,
+		}
+	}
+
+	fn atom(&self) -> Option<&str> {
+		match *self {
+			Sexpr::Atom(ref atom, _) => Some(atom),
+			_ => None,
+		}
+	}
+}
+
+fn parse(tokens: &[(Token, usize)], position: &mut usize) -> Result<Sexpr, WatError> {
+	let (token, line) = match tokens.get(*position) {
+		Some(&(ref token, line)) => (token, line),
+		None => return error(tokens.last().map_or(1, |&(_, line)| line), "unexpected end of text"),
+	};
+	*position += 1;
+	match *token {
+		Token::Open => {
+			let mut items = Vec::new();
+			loop {
+				match tokens.get(*position) {
+					Some(&(Token::Close, _)) => {
+						*position += 1;
+						return Ok(Sexpr::List(items, line));
+					}
+					Some(_) => items.push(parse(tokens, position)?),
+					None => return error(line, "unclosed parenthesis"),
+				}
+			}
+		}
+		Token::Close => error(line, "unexpected `)`"),
+		Token::Atom(ref atom) => Ok(Sexpr::Atom(atom.clone(), line)),
+		Token::Text(ref bytes) => Ok(Sexpr::Text(bytes.clone(), line)),
+	}
+}
+
+/* The first atom of a list, which says what it is */
+fn keyword(items: &[Sexpr]) -> Option<&str> {
+	items.first().and_then(Sexpr::atom)
+}
+
+fn is_id(atom: &str) -> bool {
+	atom.len() > 1 && atom.starts_with('$') && atom[1..].chars().all(|c| c.is_ascii_graphic() && c != ',' && c != '[' && c != ']' && c != '{' && c != '}')
+}
+
+/* Consumes an optional $name */
+fn take_id(items: &[Sexpr], cursor: &mut usize) -> Option<String> {
+	match items.get(*cursor).and_then(Sexpr::atom) {
+		Some(atom) if is_id(atom) => {
+			*cursor += 1;
+			Some(atom.to_string())
+		}
+		_ => None,
+	}
+}
+
+/* Consumes the lists starting with `word`, like the (export "name") of a definition */
+fn take_lists<'a>(items: &'a [Sexpr], cursor: &mut usize, word: &str) -> Vec<&'a Sexpr> {
+	let mut lists = Vec::new();
+	while let Some(list @ Sexpr::List(ref inner, _)) = items.get(*cursor) {
+		if keyword(inner) != Some(word) {
+			break;
+		}
+		lists.push(list);
+		*cursor += 1;
+	}
+	lists
+}
+
+/* An integer literal: decimal or 0x hexadecimal, signed, with _ between digits */
+fn integer(atom: &str) -> Option<i128> {
+	let (negative, digits) = match atom.as_bytes().first() {
+		Some(b'-') => (true, &atom[1..]),
+		Some(b'+') => (false, &atom[1..]),
+		_ => (false, atom),
+	};
+	let (radix, digits) = match digits.strip_prefix("0x") {
+		Some(hexadecimal) => (16, hexadecimal),
+		None => (10, digits),
+	};
+	let valid = digits.chars().all(|c| c == '_' || c.is_digit(radix));
+	if !valid || digits.is_empty() || digits.starts_with('_') || digits.ends_with('_') || digits.contains("__") {
+		return None;
+	}
+	let magnitude = i128::from_str_radix(&digits.replace('_', ""), radix).ok()?;
+	Some(if negative { -magnitude } else { magnitude })
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+enum ValType {
+	I32,
+	I64,
+}
+
+impl ValType {
+	fn from_name(name: &str) -> Option<ValType> {
+		match name {
+			"i32" => Some(ValType::I32),
+			"i64" => Some(ValType::I64),
+			_ => None,
+		}
+	}
+
+	/* Range of the integer literals a const instruction accepts, signed or unsigned */
+	fn accepts(self, value: i128) -> bool {
+		match self {
+			ValType::I32 => (-(1 << 31)..1 << 32).contains(&value),
+			ValType::I64 => (-(1 << 63)..1 << 64).contains(&value),
+		}
+	}
+}
+
+impl fmt::Display for ValType {
+	fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+		match *self {
+			ValType::I32 => write!(f, "i32"),
+			ValType::I64 => write!(f, "i64"),
+		}
+	}
+}
+
+fn types(items: &[Sexpr]) -> Result<Vec<ValType>, WatError> {
+	items.iter()
+		.map(|item| match item.atom().and_then(ValType::from_name) {
+			Some(kind) => Ok(kind),
+			None => error(item.line(), "expected a value type, i32 or i64"),
+		})
+		.collect()
+}
+
+fn type_list(kinds: &[ValType]) -> String {
+	if kinds.is_empty() {
+		"nothing".to_string()
+	} else {
+		kinds.iter().map(|kind| kind.to_string()).collect::<Vec<String>>().join(" ")
+	}
+}
+
+/* Things of one kind, numbered in the order they are defined, some of them named */
+struct Space<T> {
+	kind: &'static str,
+	items: Vec<T>,
+	names: HashMap<String, usize>,
+}
+
+impl<T> Space<T> {
+	fn new(kind: &'static str) -> Space<T> {
+		Space { kind, items: Vec::new(), names: HashMap::new() }
+	}
+
+	fn add(&mut self, name: Option<String>, item: T, line: usize) -> Result<usize, WatError> {
+		if let Some(name) = name {
+			if self.names.contains_key(&name) {
+				return error(line, format!("{} `{}` is defined twice", self.kind, name));
+			}
+			self.names.insert(name, self.items.len());
+		}
+		self.items.push(item);
+		Ok(self.items.len() - 1)
+	}
+
+	/* A $name or an index */
+	fn resolve(&self, reference: Option<&Sexpr>, line: usize) -> Result<&T, WatError> {
+		let atom = match reference.and_then(Sexpr::atom) {
+			Some(atom) => atom,
+			None => return error(line, format!("expected a {} name or index", self.kind)),
+		};
+		let index = if is_id(atom) {
+			self.names.get(atom).cloned()
+		} else {
+			integer(atom).filter(|&index| index >= 0 && (index as usize) < self.items.len()).map(|index| index as usize)
+		};
+		match index {
+			Some(index) => Ok(&self.items[index]),
+			None => error(line, format!("unknown {} `{}`", self.kind, atom)),
+		}
+	}
+}
+
+/* A parameter or local, named or not */
+type Declaration = (Option<String>, ValType);
+
+#[derive(Clone, Debug)]
+struct Signature {
+	parameters: Vec<ValType>,
+	results: Vec<ValType>,
+}
+
+#[derive(Clone, Copy, Debug)]
+struct Global {
+	kind: ValType,
+	mutable: bool,
+}
+
+/* What the fields define, and what is left to check once all of them are known */
+struct Module<'a> {
+	functions: Space<Signature>,
+	globals: Space<Global>,
+	memories: Space<u32>,			/* Minimum size in pages */
+	exports: HashMap<String, usize>,
+	bodies: Vec<(usize, &'a [Sexpr], Vec<Declaration>, usize)>,	/* Function, rest of its list, parameters, line */
+	data: Vec<&'a [Sexpr]>,
+	export_fields: Vec<&'a [Sexpr]>,
+}
+
+impl<'a> Module<'a> {
+	fn check(fields: &'a [Sexpr]) -> Result<(), WatError> {
+		let mut module = Module {
+			functions: Space::new("function"),
+			globals: Space::new("global"),
+			memories: Space::new("memory"),
+			exports: HashMap::new(),
+			bodies: Vec::new(),
+			data: Vec::new(),
+			export_fields: Vec::new(),
+		};
+		let mut defined = false;
+		for field in fields {
+			let (items, line) = match *field {
+				Sexpr::List(ref items, line) => (items, line),
+				_ => return error(field.line(), "expected a module field in parentheses"),
+			};
+			match keyword(items) {
+				Some("import") => {
+					if defined {
+						return error(line, "imports have to come before every definition");
+					}
+					module.import(items, line)?;
+				}
+				Some("func") => {
+					defined = true;
+					module.function(items, line)?;
+				}
+				Some("memory") => {
+					defined = true;
+					module.memory(items, line)?;
+				}
+				Some("global") => {
+					defined = true;
+					module.global(items, line)?;
+				}
+				Some("data") => module.data.push(items),
+				Some("export") => module.export_fields.push(items),
+				Some(other) => return error(line, format!("unsupported module field `{}`", other)),
+				None => return error(line, "expected a module field"),
+			}
+		}
+
+		for items in module.export_fields.clone() {
+			module.export_field(items)?;
+		}
+		for items in module.data.clone() {
+			module.data_segment(items)?;
+		}
+		for &(index, body, ref parameters, line) in &module.bodies {
+			Body::check(&module, &module.functions.items[index], parameters, body, line)?;
+		}
+		Ok(())
+	}
+
+	fn add_export(&mut self, list: &Sexpr) -> Result<(), WatError> {
+		let (items, line) = match *list {
+			Sexpr::List(ref items, line) => (items, line),
+			_ => unreachable!(),
+		};
+		match items.get(1) {
+			Some(Sexpr::Text(name, _)) if items.len() == 2 => {
+				let name = String::from_utf8_lossy(name).into_owned();
+				if let Some(first) = self.exports.insert(name.clone(), line) {
+					return error(line, format!("export `{}` is already defined on line {}", name, first));
+				}
+				Ok(())
+			}
+			_ => error(line, "expected `(export \"name\")`"),
+		}
+	}
+
+	/* (param $x i64) or (param i64 i32), (result i64 ...) */
+	fn signature(items: &[Sexpr], cursor: &mut usize) -> Result<(Signature, Vec<Declaration>), WatError> {
+		let mut parameters: Vec<Declaration> = Vec::new();
+		for list in take_lists(items, cursor, "param") {
+			parameters.extend(Module::declarations(list)?);
+		}
+		let mut results = Vec::new();
+		for list in take_lists(items, cursor, "result") {
+			if let Sexpr::List(ref inner, _) = *list {
+				results.extend(types(&inner[1..])?);
+			}
+		}
+		let signature = Signature { parameters: parameters.iter().map(|&(_, kind)| kind).collect(), results };
+		Ok((signature, parameters))
+	}
+
+	/* The contents of a param or local list */
+	fn declarations(list: &Sexpr) -> Result<Vec<Declaration>, WatError> {
+		let (items, line) = match *list {
+			Sexpr::List(ref items, line) => (items, line),
+			_ => unreachable!(),
+		};
+		let mut cursor = 1;
+		match take_id(items, &mut cursor) {
+			Some(name) if items.len() == 3 => Ok(vec![(Some(name), types(&items[2..])?[0])]),
+			Some(_) => error(line, "a named declaration has exactly one type"),
+			None => Ok(types(&items[1..])?.into_iter().map(|kind| (None, kind)).collect()),
+		}
+	}
+
+	fn import(&mut self, items: &'a [Sexpr], line: usize) -> Result<(), WatError> {
+		match (items.get(1), items.get(2), items.get(3)) {
+			(Some(&Sexpr::Text(..)), Some(&Sexpr::Text(..)), Some(&Sexpr::List(ref description, inner))) if items.len() == 4 => {
+				let mut cursor = 1;
+				let name = take_id(description, &mut cursor);
+				match keyword(description) {
+					Some("func") => {
+						let (signature, _) = Module::signature(description, &mut cursor)?;
+						if cursor < description.len() {
+							return error(inner, "an imported function has no body");
+						}
+						self.functions.add(name, signature, inner)?;
+					}
+					Some("global") => {
+						let global = Module::global_type(description.get(cursor), inner)?;
+						self.globals.add(name, global, inner)?;
+					}
+					Some("memory") => {
+						let minimum = Module::limits(&description[cursor..], inner)?;
+						self.memories.add(name, minimum, inner)?;
+					}
+					_ => return error(inner, "expected an imported func, global or memory"),
+				}
+				Ok(())
+			}
+			_ => error(line, "expected `(import \"module\" \"name\" (...))`"),
+		}
+	}
+
+	fn function(&mut self, items: &'a [Sexpr], line: usize) -> Result<(), WatError> {
+		let mut cursor = 1;
+		let name = take_id(items, &mut cursor);
+		for export in take_lists(items, &mut cursor, "export") {
+			self.add_export(export)?;
+		}
+		let (signature, parameters) = Module::signature(items, &mut cursor)?;
+		let index = self.functions.add(name, signature, line)?;
+		self.bodies.push((index, &items[cursor..], parameters, line));
+		Ok(())
+	}
+
+	/* Minimum and optional maximum size in pages, returns the minimum */
+	fn limits(items: &[Sexpr], line: usize) -> Result<u32, WatError> {
+		let sizes: Vec<Option<i128>> = items.iter().map(|item| item.atom().and_then(integer)).collect();
+		match sizes.as_slice() {
+			[Some(minimum)] if (0..=65536).contains(minimum) => Ok(*minimum as u32),
+			[Some(minimum), Some(maximum)] if (0..=65536).contains(minimum) && (*minimum..=65536).contains(maximum) => Ok(*minimum as u32),
+			_ => error(line, "expected a minimum and an optional maximum number of 64 KiB pages, at most 65536"),
+		}
+	}
+
+	fn memory(&mut self, items: &'a [Sexpr], line: usize) -> Result<(), WatError> {
+		let mut cursor = 1;
+		let name = take_id(items, &mut cursor);
+		for export in take_lists(items, &mut cursor, "export") {
+			self.add_export(export)?;
+		}
+		let minimum = Module::limits(&items[cursor..], line)?;
+		self.memories.add(name, minimum, line)?;
+		Ok(())
+	}
+
+	/* i64 or (mut i64) */
+	fn global_type(item: Option<&Sexpr>, line: usize) -> Result<Global, WatError> {
+		match item {
+			Some(Sexpr::Atom(atom, _)) => match ValType::from_name(atom) {
+				Some(kind) => Ok(Global { kind, mutable: false }),
+				None => error(line, "expected a global type"),
+			},
+			Some(Sexpr::List(items, _)) if keyword(items) == Some("mut") && items.len() == 2 => {
+				Ok(Global { kind: types(&items[1..])?[0], mutable: true })
+			}
+			_ => error(line, "expected a global type"),
+		}
+	}
+
+	fn global(&mut self, items: &'a [Sexpr], line: usize) -> Result<(), WatError> {
+		let mut cursor = 1;
+		let name = take_id(items, &mut cursor);
+		for export in take_lists(items, &mut cursor, "export") {
+			self.add_export(export)?;
+		}
+		let global = Module::global_type(items.get(cursor), line)?;
+		match items.get(cursor + 1) {
+			Some(&Sexpr::List(ref initial, inner)) if items.len() == cursor + 2 => Module::constant(initial, global.kind, inner)?,
+			_ => return error(line, "expected the initial value of the global"),
+		}
+		self.globals.add(name, global, line)?;
+		Ok(())
+	}
+
+	/* A constant expression: (i64.const 0) */
+	fn constant(items: &[Sexpr], kind: ValType, line: usize) -> Result<(), WatError> {
+		let wanted = format!("{}.const", kind);
+		match (keyword(items), items.get(1).and_then(Sexpr::atom).and_then(integer)) {
+			(Some(instruction), Some(value)) if instruction == wanted && items.len() == 2 && kind.accepts(value) => Ok(()),
+			_ => error(line, format!("expected `({} value)`", wanted)),
+		}
+	}
+
+	fn export_field(&mut self, items: &'a [Sexpr]) -> Result<(), WatError> {
+		let line = items[0].line();
+		let description = match items.get(2) {
+			Some(Sexpr::List(description, _)) if items.len() == 3 => description,
+			_ => return error(line, "expected `(export \"name\" (func $name))`"),
+		};
+		match keyword(description) {
+			Some("func") => self.functions.resolve(description.get(1), line).map(|_| ())?,
+			Some("global") => self.globals.resolve(description.get(1), line).map(|_| ())?,
+			Some("memory") => self.memories.resolve(description.get(1), line).map(|_| ())?,
+			_ => return error(line, "expected an exported func, global or memory"),
+		}
+		self.add_export(&Sexpr::List(items[..2].to_vec(), line))
+	}
+
+	/* (data (i32.const offset) "bytes" ...) */
+	fn data_segment(&mut self, items: &'a [Sexpr]) -> Result<(), WatError> {
+		let line = items[0].line();
+		let mut cursor = 1;
+		take_id(items, &mut cursor);
+		let offset = match items.get(cursor) {
+			Some(&Sexpr::List(ref offset, inner)) => {
+				let offset: &[Sexpr] = match keyword(offset) {
+					Some("offset") if offset.len() == 2 => match offset[1] {
+						Sexpr::List(ref expression, _) => expression,
+						_ => return error(inner, "expected `(i32.const offset)`"),
+					},
+					_ => offset,
+				};
+				Module::constant(offset, ValType::I32, inner)?;
+				integer(offset[1].atom().unwrap()).unwrap() as u32 as u64
+			}
+			_ => return error(line, "expected the offset of the data as `(i32.const offset)`"),
+		};
+		let mut length = 0;
+		for item in &items[cursor + 1..] {
+			match *item {
+				Sexpr::Text(ref bytes, _) => length += bytes.len() as u64,
+				_ => return error(item.line(), "expected a string of data"),
+			}
+		}
+		let pages = match self.memories.items.first() {
+			Some(&pages) => pages,
+			None => return error(line, "data without a memory to go into"),
+		};
+		if offset + length > u64::from(pages) * 65536 {
+			return error(line, format!("{} bytes of data at offset {} do not fit in {} pages of memory", length, offset, pages));
+		}
+		Ok(())
+	}
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+enum Kind {
+	Function,
+	Block,
+	Loop,
+	If,
+	Else,
+}
+
+/* A block being checked */
+struct Frame {
+	kind: Kind,
+	label: Option<String>,
+	results: Vec<ValType>,
+	height: usize,			/* Depth of the operand stack when the block started */
+	unreachable: bool,		/* After br, return or unreachable anything can be popped */
+}
+
+impl Frame {
+	/* What a branch to the block has to carry */
+	fn branch_types(&self) -> Vec<ValType> {
+		if self.kind == Kind::Loop { Vec::new() } else { self.results.clone() }
+	}
+}
+
+/* The operand stack typing of one function body, as the specification's validation algorithm does it */
+struct Body<'a> {
+	module: &'a Module<'a>,
+	locals: Space<ValType>,
+	values: Vec<Option<ValType>>,	/* None where the type is not known, past an unreachable point */
+	frames: Vec<Frame>,
+}
+
+impl<'a> Body<'a> {
+	fn check(module: &'a Module<'a>, signature: &Signature, parameters: &[Declaration], items: &[Sexpr], line: usize) -> Result<(), WatError> {
+		let mut body = Body { module, locals: Space::new("local"), values: Vec::new(), frames: Vec::new() };
+		for &(ref name, kind) in parameters {
+			body.locals.add(name.clone(), kind, line)?;
+		}
+		let mut cursor = 0;
+		for list in take_lists(items, &mut cursor, "local") {
+			for (name, kind) in Module::declarations(list)? {
+				body.locals.add(name, kind, list.line())?;
+			}
+		}
+		body.frames.push(Frame { kind: Kind::Function, label: None, results: signature.results.clone(), height: 0, unreachable: false });
+
+		let mut last = line;
+		while cursor < items.len() {
+			let (instruction, line) = match items[cursor] {
+				Sexpr::Atom(ref atom, line) => (atom.as_str(), line),
+				ref other => return error(other.line(), "expected an instruction, folded instructions are not supported"),
+			};
+			cursor += 1;
+			last = line;
+			body.instruction(instruction, items, &mut cursor, line)?;
+		}
+		if body.frames.len() > 1 {
+			return error(last, "block is not closed with `end`");
+		}
+		body.end("the function", last)
+	}
+
+	fn pop(&mut self, expected: Option<ValType>, instruction: &str, line: usize) -> Result<Option<ValType>, WatError> {
+		let frame = self.frames.last().unwrap();
+		if self.values.len() == frame.height {
+			if frame.unreachable {
+				return Ok(expected);
+			}
+			return match expected {
+				Some(kind) => error(line, format!("`{}` expects an {} on the stack, finds none", instruction, kind)),
+				None => error(line, format!("`{}` expects a value on the stack, finds none", instruction)),
+			};
+		}
+		let actual = self.values.pop().unwrap();
+		match (actual, expected) {
+			(Some(actual), Some(expected)) if actual != expected => {
+				error(line, format!("`{}` expects an {} on the stack, finds an {}", instruction, expected, actual))
+			}
+			_ => Ok(actual.or(expected)),
+		}
+	}
+
+	fn pop_all(&mut self, kinds: &[ValType], instruction: &str, line: usize) -> Result<(), WatError> {
+		for &kind in kinds.iter().rev() {
+			self.pop(Some(kind), instruction, line)?;
+		}
+		Ok(())
+	}
+
+	fn push_all(&mut self, kinds: &[ValType]) {
+		self.values.extend(kinds.iter().map(|&kind| Some(kind)));
+	}
+
+	/* Nothing after a branch runs until the block ends */
+	fn unreachable(&mut self) {
+		let frame = self.frames.last_mut().unwrap();
+		self.values.truncate(frame.height);
+		frame.unreachable = true;
+	}
+
+	/* The innermost block must leave exactly its results */
+	fn end(&mut self, what: &str, line: usize) -> Result<(), WatError> {
+		let results = self.frames.last().unwrap().results.clone();
+		self.pop_all(&results, "end", line)?;
+		let height = self.frames.last().unwrap().height;
+		if self.values.len() > height {
+			return error(line, format!("{} leaves {} values too many on the stack, it should leave {}", what, self.values.len() - height, type_list(&results)));
+		}
+		Ok(())
+	}
+
+	/* A $label or a relative depth, the frame it names */
+	fn label(&self, reference: Option<&Sexpr>, line: usize) -> Result<usize, WatError> {
+		let atom = match reference.and_then(Sexpr::atom) {
+			Some(atom) => atom,
+			None => return error(line, "expected a label"),
+		};
+		let frame = if is_id(atom) {
+			self.frames.iter().rposition(|frame| frame.label.as_ref().is_some_and(|label| label == atom))
+		} else {
+			integer(atom).filter(|&depth| depth >= 0 && (depth as usize) < self.frames.len()).map(|depth| self.frames.len() - 1 - depth as usize)
+		};
+		match frame {
+			Some(frame) => Ok(frame),
+			None => error(line, format!("unknown label `{}`", atom)),
+		}
+	}
+
+	fn instruction(&mut self, instruction: &str, items: &[Sexpr], cursor: &mut usize, line: usize) -> Result<(), WatError> {
+		let module = self.module;
+		match instruction {
+			"block" | "loop" | "if" => {
+				let label = take_id(items, cursor);
+				let mut results = Vec::new();
+				for list in take_lists(items, cursor, "result") {
+					if let Sexpr::List(ref inner, _) = *list {
+						results.extend(types(&inner[1..])?);
+					}
+				}
+				if instruction == "if" {
+					self.pop(Some(ValType::I32), instruction, line)?;
+				}
+				let kind = match instruction {
+					"block" => Kind::Block,
+					"loop" => Kind::Loop,
+					_ => Kind::If,
+				};
+				self.frames.push(Frame { kind, label, results, height: self.values.len(), unreachable: false });
+			}
+			"else" => {
+				take_id(items, cursor);
+				if self.frames.last().unwrap().kind != Kind::If {
+					return error(line, "`else` outside of an `if`");
+				}
+				self.end("the `if` branch", line)?;
+				let frame = self.frames.last_mut().unwrap();
+				frame.kind = Kind::Else;
+				frame.unreachable = false;
+			}
+			"end" => {
+				take_id(items, cursor);
+				if self.frames.len() == 1 {
+					return error(line, "`end` without a block to close");
+				}
+				self.end("the block", line)?;
+				let frame = self.frames.pop().unwrap();
+				if frame.kind == Kind::If && !frame.results.is_empty() {
+					return error(line, "an `if` with results needs an `else`");
+				}
+				self.push_all(&frame.results);
+			}
+			"br" | "br_if" => {
+				let target = self.label(items.get(*cursor), line)?;
+				*cursor += 1;
+				if instruction == "br_if" {
+					self.pop(Some(ValType::I32), instruction, line)?;
+				}
+				let kinds = self.frames[target].branch_types();
+				self.pop_all(&kinds, instruction, line)?;
+				if instruction == "br" {
+					self.unreachable();
+				} else {
+					self.push_all(&kinds);
+				}
+			}
+			"br_table" => {
+				let mut targets = Vec::new();
+				while let Some(atom) = items.get(*cursor).and_then(Sexpr::atom) {
+					if !is_id(atom) && integer(atom).is_none() {
+						break;
+					}
+					targets.push(self.label(items.get(*cursor), line)?);
+					*cursor += 1;
+				}
+				let default = match targets.last() {
+					Some(&default) => self.frames[default].branch_types(),
+					None => return error(line, "`br_table` needs at least a default label"),
+				};
+				if targets.iter().any(|&target| self.frames[target].branch_types() != default) {
+					return error(line, "the labels of a `br_table` carry different types");
+				}
+				self.pop(Some(ValType::I32), instruction, line)?;
+				self.pop_all(&default, instruction, line)?;
+				self.unreachable();
+			}
+			"return" => {
+				let results = self.frames[0].results.clone();
+				self.pop_all(&results, instruction, line)?;
+				self.unreachable();
+			}
+			"unreachable" => self.unreachable(),
+			"nop" => {}
+			"drop" => {
+				self.pop(None, instruction, line)?;
+			}
+			"local.get" | "local.set" | "local.tee" => {
+				let kind = *self.locals.resolve(items.get(*cursor), line)?;
+				*cursor += 1;
+				if instruction != "local.get" {
+					self.pop(Some(kind), instruction, line)?;
+				}
+				if instruction != "local.set" {
+					self.push_all(&[kind]);
+				}
+			}
+			"global.get" | "global.set" => {
+				let global = *module.globals.resolve(items.get(*cursor), line)?;
+				*cursor += 1;
+				if instruction == "global.set" {
+					if !global.mutable {
+						return error(line, "`global.set` of an immutable global");
+					}
+					self.pop(Some(global.kind), instruction, line)?;
+				} else {
+					self.push_all(&[global.kind]);
+				}
+			}
+			"call" => {
+				let signature = module.functions.resolve(items.get(*cursor), line)?.clone();
+				*cursor += 1;
+				self.pop_all(&signature.parameters, instruction, line)?;
+				self.push_all(&signature.results);
+			}
+			"i32.const" | "i64.const" => {
+				let kind = ValType::from_name(&instruction[..3]).unwrap();
+				match items.get(*cursor).and_then(Sexpr::atom).and_then(integer) {
+					Some(value) if kind.accepts(value) => *cursor += 1,
+					_ => return error(line, format!("`{}` needs an integer that fits in {}", instruction, kind)),
+				}
+				self.push_all(&[kind]);
+			}
+			_ => match numeric(instruction) {
+				Some((parameters, results)) => {
+					self.pop_all(&parameters, instruction, line)?;
+					self.push_all(&results);
+				}
+				None => return error(line, format!("unknown or unsupported instruction `{}`", instruction)),
+			},
+		}
+		Ok(())
+	}
+}
+
+/* Parameter and result types of an integer instruction like i64.add or i32.eqz */
+fn numeric(instruction: &str) -> Option<(Vec<ValType>, Vec<ValType>)> {
+	let (kind, operation) = match instruction.split_once('.') {
+		Some((kind, operation)) => (ValType::from_name(kind)?, operation),
+		None => return None,
+	};
+	match (kind, operation) {
+		(_, "add") | (_, "sub") | (_, "mul") | (_, "div_s") | (_, "div_u") | (_, "rem_s") | (_, "rem_u")
+		| (_, "and") | (_, "or") | (_, "xor") | (_, "shl") | (_, "shr_s") | (_, "shr_u") | (_, "rotl") | (_, "rotr") => {
+			Some((vec![kind, kind], vec![kind]))
+		}
+		(_, "eq") | (_, "ne") | (_, "lt_s") | (_, "lt_u") | (_, "gt_s") | (_, "gt_u") | (_, "le_s") | (_, "le_u")
+		| (_, "ge_s") | (_, "ge_u") => Some((vec![kind, kind], vec![ValType::I32])),
+		(_, "eqz") => Some((vec![kind], vec![ValType::I32])),
+		(_, "clz") | (_, "ctz") | (_, "popcnt") => Some((vec![kind], vec![kind])),
+		(ValType::I64, "extend_i32_s") | (ValType::I64, "extend_i32_u") => Some((vec![ValType::I32], vec![ValType::I64])),
+		(ValType::I32, "wrap_i64") => Some((vec![ValType::I64], vec![ValType::I32])),
+		_ => None,
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::validate;
+
+	/* A module with one function of the given signature and body */
+	fn function(signature: &str, body: &str) -> String {
+		format!("(module\n\t(func $f {}\n{}\n\t)\n)\n", signature, body)
+	}
+
+	/* The message of the error, which has to be on the given line */
+	fn rejected(text: &str, line: usize) -> String {
+		let why = validate(text).unwrap_err();
+		assert_eq!(why.line, line, "{}", why);
+		why.message
+	}
+
+	#[test]
+	fn accepts_what_the_backend_writes() {
+		let module = "(module
+	(import \"jaz\" \"print\" (func $print (param i64)))
+	(memory (export \"memory\") 1)
+	(data (i32.const 0) \"hi\\0a\")
+	(global $n (mut i64) (i64.const 0))
+	(func $twice (param $x i64) (result i64 i64)
+		(local $pc i32)
+		local.get $x
+		local.get $x
+		i64.const 2
+		i64.mul
+	)
+	(func $main (export \"main\")
+		(local $pc i32)
+		loop $dispatch
+			block $piece_1
+				block $piece_0
+					local.get $pc
+					br_table $piece_0 $piece_1
+				end
+				global.get $n
+				call $twice
+				i64.add
+				global.set $n
+				i32.const 1
+				local.set $pc
+				br $dispatch
+			end
+		end
+		global.get $n
+		i64.eqz
+		if
+			global.get $n
+			call $print
+		else
+			return
+		end
+	)
+)
+";
+		assert_eq!(validate(module), Ok(()));
+
+		for source in &[include_str!("../../factProc.jaz"), include_str!("../../demo.jaz"), include_str!("../../operatorsTest.jaz")] {
+			let program = ::parse(source).unwrap();
+			assert!(::codegen::wat::generate(&program).is_ok());
+		}
+	}
+
+	#[test]
+	fn rejects_type_mismatches() {
+		let message = rejected(&function("", "i32.const 1\ni64.const 2\ni64.add\ndrop"), 5);
+		assert!(message.contains("expects an i64"), "{}", message);
+		let message = rejected(&function("(result i32)", "i64.const 1"), 3);
+		assert!(message.contains("i32"), "{}", message);
+	}
+
+	#[test]
+	fn rejects_unknown_labels() {
+		let message = rejected(&function("", "block $out\nbr $nope\nend"), 4);
+		assert_eq!(message, "unknown label `$nope`");
+		/* Relative depths count the enclosing blocks */
+		rejected(&function("", "block\nbr 2\nend"), 4);
+		assert_eq!(validate(&function("", "block\nbr 1\nend")), Ok(()));
+	}
+
+	#[test]
+	fn rejects_wrong_block_results() {
+		rejected(&function("", "block (result i64)\nend"), 4);
+		let message = rejected(&function("", "block\ni64.const 1\nend"), 5);
+		assert!(message.contains("too many"), "{}", message);
+		assert_eq!(validate(&function("(result i64)", "block (result i64)\ni64.const 1\nend")), Ok(()));
+	}
+
+	#[test]
+	fn rejects_bad_local_indices() {
+		let message = rejected(&function("(param i64) (local i64)", "local.get 2\ndrop"), 3);
+		assert!(message.contains("unknown local"), "{}", message);
+		assert_eq!(validate(&function("(param i64) (local i64)", "local.get 1\ndrop")), Ok(()));
+		rejected(&function("", "local.get $x\ndrop"), 3);
+	}
+
+	#[test]
+	fn rejects_malformed_modules() {
+		rejected("(module\n\t(func $f)\n\t(func $f)\n)\n", 3);
+		rejected("(module\n\t(memory 1)\n\t(data (i32.const 65535) \"ab\")\n)\n", 3);
+		rejected("(module\n\t(func (export \"a\"))\n\t(func (export \"a\"))\n)\n", 3);
+		rejected("(module\n\t(func $f\n\t\tcall $g\n\t)\n)\n", 3);
+		rejected("(module) (module)", 1);
+	}
+}
@@ -0,0 +1,312 @@
+/* x86-64 Backend
+ * Writes assembly for the GNU assembler (AT&T syntax) following the System V
+ * ABI where it meets the C library, to be built with `cc program.s`. The
+ * program entry's own frame lives in .bss, every other variable and stack
+ * slot in the frame of the function it belongs to, so recursion gets fresh
+ * ones. Expressions are evaluated into %rax, with the hardware stack holding
+ * the operands waiting for the right-hand side, much as the jaz stack would.
+ *
+ * Subroutines use a convention of their own: the caller pushes one argument
+ * per parameter the subroutine does not keep local, last one first, a value
+ * or the address of a variable the subroutine hands back, and removes them
+ * after the call. Jumps stay jumps, no structuring is needed.
+ *
+ * Output goes through a small runtime at the end of the file, which aligns
+ * the stack before calling printf, puts or exit.
+ */
+use codegen::{lower, Argument, BinaryOp, CodegenError, Expr, Frame, Function, Mode, Module, Names, Statement, Variable};
+use codegen::{DIVISION_BY_ZERO, RUNTIME_ERROR_STATUS};
+use parsetree::Program;
+
+pub fn generate(program: &Program) -> Result<String, CodegenError> {
+	let module = lower(program)?;
+	/* Symbols are prefixed by what they are, so they cannot clash with each other or the C library */
+	let names = Names::new(&module, &[]);
+
+	let mut texts: Vec<&str> = Vec::new();
+	for statement in module.functions.iter().flat_map(|function| &function.body) {
+		if let Statement::Show(ref text) = *statement {
+			if !texts.contains(&text.as_str()) {
+				texts.push(text);
+			}
+		}
+	}
+
+	let mut out = String::from("\t.text\n");
+	for function in &module.functions {
+		let context = Context { function, names: &names, texts: &texts, offsets: offsets(function) };
+		context.function(&mut out);
+	}
+	write_runtime(&mut out, &module);
+
+	if !module.variables.is_empty() {
+		out.push_str("\n\t.bss\n\t.p2align 3\n");
+		let main = &module.functions[0];
+		for variable in &module.variables {
+			out.push_str(&format!("{}:\n\t.zero 8\n", symbol("g_", names.variable(main, &Variable::new(Frame::Own, variable)))));
+		}
+	}
+	out.push_str("\n\t.section .rodata\n");
+	for (number, text) in texts.iter().enumerate() {
+		out.push_str(&format!(".Lshow{}:\n\t.asciz {}\n", number, string_literal(text)));
+	}
+	if module.applies(BinaryOp::Div) || module.applies(BinaryOp::Rem) {
+		out.push_str(&format!(".Ldivision_by_zero:\n\t.asciz {}\n", string_literal(&format!("{}\n", DIVISION_BY_ZERO))));
+	}
+	if module.functions.iter().flat_map(|function| &function.body).any(|statement| matches!(*statement, Statement::Print(_))) {
+		out.push_str(".Lprint_format:\n\t.asciz \"%ld\\n\"\n");
+	}
+	out.push_str("\n\t.section .note.GNU-stack,\"\",@progbits\n");
+	Ok(out)
+}
+
+/* Where each variable lives inside a function's frame, relative to %rbp */
+struct Offsets {
+	arguments: Vec<(String, Mode)>,	/* Parameter names the caller pushed, at 16(%rbp) upwards */
+	frame: Vec<Variable>,			/* Local parameters and begin block variables, at -8(%rbp) downwards */
+	slots: usize,					/* Stack slots, below the variables */
+}
+
+fn offsets(function: &Function) -> Offsets {
+	let mut offsets = Offsets { arguments: Vec::new(), frame: Vec::new(), slots: function.slots };
+	if function.label.is_some() {
+		for parameter in &function.parameters {
+			if parameter.mode == Mode::Local {
+				offsets.frame.push(Variable::new(Frame::Own, &parameter.name));
+			} else {
+				offsets.arguments.push((parameter.name.clone(), parameter.mode));
+			}
+		}
+	}
+	offsets.frame.extend(function.locals.iter().cloned());
+	offsets
+}
+
+/* Printing, showing, stopping and dividing. The C library wants the stack 16-byte aligned */
+fn write_runtime(out: &mut String, module: &Module) {
+	let statements = || module.functions.iter().flat_map(|function| &function.body);
+	let called = |wanted: fn(&Statement) -> bool| statements().any(wanted);
+	let dividing = module.applies(BinaryOp::Div) || module.applies(BinaryOp::Rem);
+
+	if called(|statement| matches!(*statement, Statement::Print(_))) {
+		out.push_str("\n/* Prints %rax and a newline */\njaz_print:\n");
+		out.push_str("\tpushq %rbp\n\tmovq %rsp, %rbp\n\tandq $-16, %rsp\n");
+		out.push_str("\tmovq %rax, %rsi\n\tleaq .Lprint_format(%rip), %rdi\n\txorl %eax, %eax\n\tcall printf@PLT\n");
+		out.push_str("\tleave\n\tret\n");
+	}
+	if called(|statement| matches!(*statement, Statement::Show(_))) {
+		out.push_str("\n/* Prints the text at %rax and a newline */\njaz_show:\n");
+		out.push_str("\tpushq %rbp\n\tmovq %rsp, %rbp\n\tandq $-16, %rsp\n");
+		out.push_str("\tmovq %rax, %rdi\n\tcall puts@PLT\n");
+		out.push_str("\tleave\n\tret\n");
+	}
+	if module.functions.iter().any(|function| function.label.is_some() && function.body.contains(&Statement::Halt)) {
+		out.push_str("\n/* Ends the program from inside a subroutine, exit flushes stdout */\njaz_halt:\n");
+		out.push_str("\tandq $-16, %rsp\n\txorl %edi, %edi\n\tcall exit@PLT\n");
+	}
+	if dividing {
+		/* See codegen::DIVISION_BY_ZERO. idiv faults where the interpreter wraps, so -1 is handled first */
+		out.push_str("\n/* %rax / %rcx and %rax % %rcx, into %rax */\njaz_div:\n");
+		out.push_str("\ttestq %rcx, %rcx\n\tjz jaz_division_by_zero\n\tcmpq $-1, %rcx\n\tjne 1f\n\tnegq %rax\n\tret\n");
+		out.push_str("1:\tcqto\n\tidivq %rcx\n\tret\n");
+		out.push_str("jaz_rem:\n");
+		out.push_str("\ttestq %rcx, %rcx\n\tjz jaz_division_by_zero\n\tcmpq $-1, %rcx\n\tjne 1f\n\txorl %eax, %eax\n\tret\n");
+		out.push_str("1:\tcqto\n\tidivq %rcx\n\tmovq %rdx, %rax\n\tret\n");
+		/* Output printed so far goes out before the message, fflush(NULL) flushes every stream */
+		out.push_str("jaz_division_by_zero:\n\tandq $-16, %rsp\n\txorl %edi, %edi\n\tcall fflush@PLT\n");
+		out.push_str("\tleaq .Ldivision_by_zero(%rip), %rdi\n\tmovq stderr@GOTPCREL(%rip), %rsi\n\tmovq (%rsi), %rsi\n\tcall fputs@PLT\n");
+		out.push_str(&format!("\tmovl ${}, %edi\n\tcall exit@PLT\n", RUNTIME_ERROR_STATUS));
+	}
+}
+
+/* The function being written */
+struct Context<'a> {
+	function: &'a Function,
+	names: &'a Names,
+	texts: &'a [&'a str],
+	offsets: Offsets,
+}
+
+impl<'a> Context<'a> {
+	fn function(&self, out: &mut String) {
+		let size = 8 * (self.offsets.frame.len() + self.offsets.slots);
+		match self.function.label {
+			Some(ref label) => out.push_str(&format!("\n{}:\n", symbol("f_", self.names.function(label)))),
+			None => out.push_str("\n\t.globl main\nmain:\n"),
+		}
+		out.push_str("\tpushq %rbp\n\tmovq %rsp, %rbp\n");
+		if size > 0 {
+			out.push_str(&format!("\tsubq ${}, %rsp\n", size));
+		}
+		for offset in (8..=size).step_by(8) {
+			out.push_str(&format!("\tmovq $0, -{}(%rbp)\n", offset));
+		}
+		for statement in &self.function.body {
+			self.statement(out, statement);
+		}
+	}
+
+	fn statement(&self, out: &mut String, statement: &Statement) {
+		match *statement {
+			Statement::Assign(ref variable, ref value) => {
+				self.value(out, value);
+				self.store(out, variable);
+			}
+			Statement::Spill(slot, ref value) => {
+				self.value(out, value);
+				out.push_str(&format!("\tmovq %rax, {}\n", self.slot(slot)));
+			}
+			Statement::Evaluate(ref value) => self.value(out, value),
+			Statement::Print(ref value) => {
+				self.value(out, value);
+				out.push_str("\tcall jaz_print\n");
+			}
+			Statement::Show(ref text) => {
+				let number = self.texts.iter().position(|shown| shown == text).unwrap();
+				out.push_str(&format!("\tleaq .Lshow{}(%rip), %rax\n\tcall jaz_show\n", number));
+			}
+			Statement::Call(ref label, ref arguments) => {
+				for argument in arguments.iter().rev() {
+					match *argument {
+						Argument::Value(ref variable) => self.load(out, variable),
+						Argument::Reference(ref variable) => self.address(out, variable),
+					}
+					out.push_str("\tpushq %rax\n");
+				}
+				out.push_str(&format!("\tcall {}\n", symbol("f_", self.names.function(label))));
+				if !arguments.is_empty() {
+					out.push_str(&format!("\taddq ${}, %rsp\n", 8 * arguments.len()));
+				}
+			}
+			Statement::Label(ref label) => out.push_str(&format!("{}:\n", self.label(label))),
+			Statement::Goto(ref label) => out.push_str(&format!("\tjmp {}\n", self.label(label))),
+			Statement::Branch(ref condition, jump_if, ref label) => {
+				self.value(out, condition);
+				let jump = if jump_if { "jnz" } else { "jz" };
+				out.push_str(&format!("\ttestq %rax, %rax\n\t{} {}\n", jump, self.label(label)));
+			}
+			Statement::Return => out.push_str("\tleave\n\tret\n"),
+			Statement::Halt if self.function.label.is_none() => out.push_str("\txorl %eax, %eax\n\tleave\n\tret\n"),
+			Statement::Halt => out.push_str("\tcall jaz_halt\n"),
+		}
+	}
+
+	/* Code reached from several functions is copied into each, so local labels carry the function's symbol */
+	fn label(&self, label: &str) -> String {
+		let owner = match self.function.label {
+			Some(ref own) => symbol("f_", self.names.function(own)),
+			None => "main".to_string(),
+		};
+		format!(".L_{}.{}", owner, symbol("", label))
+	}
+
+	/* The memory operand of a variable, or of the pointer to it for a parameter handed back */
+	fn location(&self, variable: &Variable) -> (String, bool) {
+		if self.function.label.is_none() && variable.frame == Frame::Own {
+			let global = self.names.variable(self.function, variable);
+			return (format!("{}(%rip)", symbol("g_", global)), false);
+		}
+		if variable.frame == Frame::Own {
+			if let Some(index) = self.offsets.arguments.iter().position(|(name, _)| *name == variable.name) {
+				return (format!("{}(%rbp)", 16 + 8 * index), self.offsets.arguments[index].1.by_pointer());
+			}
+		}
+		let index = self.offsets.frame.iter().position(|local| local == variable).unwrap();
+		(format!("-{}(%rbp)", 8 * (index + 1)), false)
+	}
+
+	fn load(&self, out: &mut String, variable: &Variable) {
+		match self.location(variable) {
+			(operand, false) => out.push_str(&format!("\tmovq {}, %rax\n", operand)),
+			(pointer, true) => out.push_str(&format!("\tmovq {}, %rax\n\tmovq (%rax), %rax\n", pointer)),
+		}
+	}
+
+	/* Stores %rax */
+	fn store(&self, out: &mut String, variable: &Variable) {
+		match self.location(variable) {
+			(operand, false) => out.push_str(&format!("\tmovq %rax, {}\n", operand)),
+			(pointer, true) => out.push_str(&format!("\tmovq {}, %rcx\n\tmovq %rax, (%rcx)\n", pointer)),
+		}
+	}
+
+	/* Pointers handed on are passed as they are */
+	fn address(&self, out: &mut String, variable: &Variable) {
+		match self.location(variable) {
+			(operand, false) => out.push_str(&format!("\tleaq {}, %rax\n", operand)),
+			(pointer, true) => out.push_str(&format!("\tmovq {}, %rax\n", pointer)),
+		}
+	}
+
+	fn slot(&self, slot: usize) -> String {
+		format!("-{}(%rbp)", 8 * (self.offsets.frame.len() + slot + 1))
+	}
+
+	/* Evaluates into %rax, left operands wait on the stack. Both sides of And and Or are evaluated */
+	fn value(&self, out: &mut String, value: &Expr) {
+		match *value {
+			Expr::Constant(constant) if i64::from(i32::MIN) <= constant && constant <= i64::from(i32::MAX) => {
+				out.push_str(&format!("\tmovq ${}, %rax\n", constant));
+			}
+			Expr::Constant(constant) => out.push_str(&format!("\tmovabsq ${}, %rax\n", constant)),
+			Expr::Variable(ref variable) => self.load(out, variable),
+			Expr::Slot(slot) => out.push_str(&format!("\tmovq {}, %rax\n", self.slot(slot))),
+			Expr::Not(ref operand) => {
+				self.value(out, operand);
+				out.push_str("\ttestq %rax, %rax\n\tsete %al\n\tmovzbl %al, %eax\n");
+			}
+			Expr::Binary(op, ref left, ref right) => {
+				self.value(out, left);
+				out.push_str("\tpushq %rax\n");
+				self.value(out, right);
+				out.push_str("\tmovq %rax, %rcx\n\tpopq %rax\n");
+				out.push_str(operation(op));
+			}
+		}
+	}
+}
+
+/* Applies `op` to %rax and %rcx, into %rax */
+fn operation(op: BinaryOp) -> &'static str {
+	match op {
+		BinaryOp::Add => "\taddq %rcx, %rax\n",
+		BinaryOp::Sub => "\tsubq %rcx, %rax\n",
+		BinaryOp::Mul => "\timulq %rcx, %rax\n",
+		BinaryOp::Div => "\tcall jaz_div\n",
+		BinaryOp::Rem => "\tcall jaz_rem\n",
+		BinaryOp::And => "\ttestq %rax, %rax\n\tsetne %al\n\ttestq %rcx, %rcx\n\tsetne %cl\n\tandb %cl, %al\n\tmovzbl %al, %eax\n",
+		BinaryOp::Or => "\ttestq %rax, %rax\n\tsetne %al\n\ttestq %rcx, %rcx\n\tsetne %cl\n\torb %cl, %al\n\tmovzbl %al, %eax\n",
+		BinaryOp::NotEqual => "\tcmpq %rcx, %rax\n\tsetne %al\n\tmovzbl %al, %eax\n",
+		BinaryOp::LessEqual => "\tcmpq %rcx, %rax\n\tsetle %al\n\tmovzbl %al, %eax\n",
+		BinaryOp::GreaterEqual => "\tcmpq %rcx, %rax\n\tsetge %al\n\tmovzbl %al, %eax\n",
+		BinaryOp::Less => "\tcmpq %rcx, %rax\n\tsetl %al\n\tmovzbl %al, %eax\n",
+		BinaryOp::Greater => "\tcmpq %rcx, %rax\n\tsetg %al\n\tmovzbl %al, %eax\n",
+		BinaryOp::Equal => "\tcmpq %rcx, %rax\n\tsete %al\n\tmovzbl %al, %eax\n",
+	}
+}
+
+/* jaz names may hold any letter, symbols only ASCII. `.` never occurs in a jaz name */
+fn symbol(prefix: &str, name: &str) -> String {
+	let mut symbol = prefix.to_string();
+	for c in name.chars() {
+		if c.is_ascii_alphanumeric() || c == '_' {
+			symbol.push(c);
+		} else {
+			symbol.push_str(&format!(".{:x}.", c as u32));
+		}
+	}
+	symbol
+}
+
+fn string_literal(text: &str) -> String {
+	let mut literal = String::from("\"");
+	for &byte in text.as_bytes() {
+		match byte {
+			b'"' | b'\\' => literal.push_str(&format!("\\{}", byte as char)),
+			b' '..=b'~' => literal.push(byte as char),
+			_ => literal.push_str(&format!("\\{:03o}", byte)),
+		}
+	}
+	literal.push('"');
+	literal
+}
@@ -0,0 +1,185 @@
+/* Diagnostics
+ * Errors and warnings from every stage of the pipeline are turned into a
+ * Diagnostic, which the Renderer prints the way rustc does: a headline, the
+ * file and position, the offending source line with a caret underline, and
+ * any notes or help text. Output is either coloured for a terminal or plain.
+ */
+use std::fmt;
+
+use tokenizer::Span;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Severity {
+	Error,
+	Warning,
+}
+
+impl fmt::Display for Severity {
+	fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+		match *self {
+			Severity::Error => write!(f, "error"),
+			Severity::Warning => write!(f, "warning"),
+		}
+	}
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Diagnostic {
+	pub severity: Severity,
+	pub message: String,
+	pub span: Option<Span>,			/* Where in the source the problem is, if anywhere */
+	pub label: Option<String>,		/* Printed next to the caret underline */
+	pub notes: Vec<String>,
+	pub help: Vec<String>,
+}
+
+impl Diagnostic {
+	pub fn error<S: Into<String>>(message: S) -> Diagnostic {
+		Diagnostic::new(Severity::Error, message.into())
+	}
+
+	pub fn warning<S: Into<String>>(message: S) -> Diagnostic {
+		Diagnostic::new(Severity::Warning, message.into())
+	}
+
+	fn new(severity: Severity, message: String) -> Diagnostic {
+		Diagnostic { severity, message, span: None, label: None, notes: Vec::new(), help: Vec::new() }
+	}
+
+	pub fn is_error(&self) -> bool {
+		self.severity == Severity::Error
+	}
+
+	pub fn at(mut self, span: Span) -> Diagnostic {
+		self.span = Some(span);
+		self
+	}
+
+	pub fn label<S: Into<String>>(mut self, label: S) -> Diagnostic {
+		self.label = Some(label.into());
+		self
+	}
+
+	pub fn note<S: Into<String>>(mut self, note: S) -> Diagnostic {
+		self.notes.push(note.into());
+		self
+	}
+
+	pub fn help<S: Into<String>>(mut self, help: S) -> Diagnostic {
+		self.help.push(help.into());
+		self
+	}
+}
+
+/* ANSI escape sequences used when colour is on */
+const RESET: &str = "\x1b[0m";
+const BOLD: &str = "\x1b[1m";
+const RED: &str = "\x1b[1;31m";
+const YELLOW: &str = "\x1b[1;33m";
+const BLUE: &str = "\x1b[1;34m";
+
+/* Tabs are expanded so the caret line stays aligned with the source line */
+const TAB_WIDTH: usize = 4;
+
+pub struct Renderer<'a> {
+	file: &'a str,
+	source: &'a str,
+	color: bool,
+}
+
+impl<'a> Renderer<'a> {
+	pub fn new(file: &'a str, source: &'a str, color: bool) -> Renderer<'a> {
+		Renderer { file, source, color }
+	}
+
+	pub fn render(&self, diagnostic: &Diagnostic) -> String {
+		let accent = match diagnostic.severity {
+			Severity::Error => RED,
+			Severity::Warning => YELLOW,
+		};
+		let mut out = String::new();
+
+		out.push_str(&format!("{}{}", self.paint(accent, &diagnostic.severity.to_string()), self.paint(BOLD, &format!(": {}", diagnostic.message))));
+		out.push('\n');
+
+		/* Without a position only the file can be named */
+		let span = match diagnostic.span {
+			Some(span) => span,
+			None => {
+				out.push_str(&format!("{} {}\n", self.paint(BLUE, "-->"), self.file));
+				self.render_footer(&mut out, diagnostic, 0);
+				return out;
+			}
+		};
+
+		let gutter = span.line.to_string().len();
+		let pad = " ".repeat(gutter);
+		let (text, start, width) = self.excerpt(span);
+
+		out.push_str(&format!("{}{} {}:{}\n", pad, self.paint(BLUE, "-->"), self.file, span));
+		out.push_str(&format!("{} {}\n", pad, self.paint(BLUE, "|")));
+		out.push_str(&format!("{} {} {}\n", self.paint(BLUE, &span.line.to_string()), self.paint(BLUE, "|"), text));
+
+		let mut underline = "^".repeat(width);
+		if let Some(ref label) = diagnostic.label {
+			underline.push(' ');
+			underline.push_str(label);
+		}
+		out.push_str(&format!("{} {} {}{}\n", pad, self.paint(BLUE, "|"), " ".repeat(start), self.paint(accent, &underline)));
+
+		self.render_footer(&mut out, diagnostic, gutter);
+		out
+	}
+
+	fn render_footer(&self, out: &mut String, diagnostic: &Diagnostic, gutter: usize) {
+		let pad = " ".repeat(gutter);
+		if gutter > 0 && !(diagnostic.notes.is_empty() && diagnostic.help.is_empty()) {
+			out.push_str(&format!("{} {}\n", pad, self.paint(BLUE, "|")));
+		}
+		for note in &diagnostic.notes {
+			out.push_str(&format!("{} {} {}: {}\n", pad, self.paint(BLUE, "="), self.paint(BOLD, "note"), note));
+		}
+		for help in &diagnostic.help {
+			out.push_str(&format!("{} {} {}: {}\n", pad, self.paint(BLUE, "="), self.paint(BOLD, "help"), help));
+		}
+	}
+
+	/* The source line holding `span` with tabs expanded, and the display column and width of the underline */
+	fn excerpt(&self, span: Span) -> (String, usize, usize) {
+		let offset = span.offset.min(self.source.len());
+		let line_start = self.source[..offset].rfind('\n').map_or(0, |newline| newline + 1);
+		let line_end = self.source[offset..].find('\n').map_or(self.source.len(), |newline| offset + newline);
+		let line = self.source[line_start..line_end].trim_end_matches('\r');
+
+		/* Spans running past the end of the line are cut off there */
+		let end = (offset + span.length).min(line_start + line.len()).max(offset);
+
+		let mut text = String::new();
+		let mut start = 0;
+		let mut width = 0;
+		for (index, c) in line.char_indices() {
+			let position = line_start + index;
+			let columns = if c == '\t' { TAB_WIDTH } else { 1 };
+			if position < offset {
+				start += columns;
+			} else if position < end {
+				width += columns;
+			}
+			if c == '\t' {
+				text.push_str(&" ".repeat(TAB_WIDTH));
+			} else {
+				text.push(c);
+			}
+		}
+
+		(text, start, width.max(1))
+	}
+
+	fn paint(&self, style: &str, text: &str) -> String {
+		if self.color {
+			format!("{}{}{}", style, text, RESET)
+		} else {
+			text.to_string()
+		}
+	}
+}
@@ -1,35 +1,104 @@
 /* File I/O Imports */
 use std::error::Error;
+use std::fmt;
 use std::fs::File;
-use std::io::prelude::*;
+use std::io::{self, Read, Write};
 use std::path::Path;
 
 pub struct FileIO;
 
-impl FileIO {
-	pub fn read_in_file(file_name: &str) -> String
-	{
-		let path = Path::new(file_name);
-		let display = path.display();
-
-		/* Open the path in read-only mode, returns `io::Result<File>` */
-		let mut file = match File::open(&path)
-		{
-			Err(why) => panic!("Couldn't read {}: {}", display, why.description()),
-			Ok(file) => file,
-		};
+/* The name `-` stands for stdin when reading and stdout when writing */
+pub const STANDARD_STREAM: &str = "-";
 
-		/* Read the file contents into a string, returns `io:Result<usize>` */
-		let mut s = String::new();
-		match file.read_to_string(&mut s)
-		{
-			Err(why) => panic!("Couldn't read {}: {}", display, why.description()),
-			Ok(_) => {
-				// print!("{} contains:\n{}", display, s)
+#[derive(Debug)]
+pub enum FileError {
+	Open(String, io::Error),		/* Path, why it couldn't be opened for reading */
+	Create(String, io::Error),
+	Read(String, io::Error),
+	Write(String, io::Error),
+	NotUtf8(String, usize, usize),	/* Path, line and byte offset of the first invalid byte */
+}
+
+impl FileError {
+	pub fn path(&self) -> &str {
+		match *self {
+			FileError::Open(ref path, _)
+			| FileError::Create(ref path, _)
+			| FileError::Read(ref path, _)
+			| FileError::Write(ref path, _)
+			| FileError::NotUtf8(ref path, _, _) => path,
+		}
+	}
+}
+
+impl fmt::Display for FileError {
+	fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+		let path = FileIO::display_name(self.path());
+		match *self {
+			FileError::Open(_, ref why) => write!(f, "couldn't open {}: {}", path, why),
+			FileError::Create(_, ref why) => write!(f, "couldn't create {}: {}", path, why),
+			FileError::Read(_, ref why) => write!(f, "couldn't read {}: {}", path, why),
+			FileError::Write(ref name, ref why) if name == STANDARD_STREAM => write!(f, "couldn't write <stdout>: {}", why),
+			FileError::Write(_, ref why) => write!(f, "couldn't write {}: {}", path, why),
+			FileError::NotUtf8(_, line, offset) => {
+				write!(f, "{} is not valid UTF-8 (first invalid byte on line {}, at byte offset {})", path, line, offset)
 			}
 		}
+	}
+}
+
+impl Error for FileError {
+	fn source(&self) -> Option<&(dyn Error + 'static)> {
+		match *self {
+			FileError::Open(_, ref why)
+			| FileError::Create(_, ref why)
+			| FileError::Read(_, ref why)
+			| FileError::Write(_, ref why) => Some(why),
+			FileError::NotUtf8(..) => None,
+		}
+	}
+}
+
+impl FileIO {
+	/* How a path is shown in messages, stdin and stdout have no path of their own */
+	pub fn display_name(file_name: &str) -> String {
+		if file_name == STANDARD_STREAM {
+			"<stdin>".to_string()
+		} else {
+			Path::new(file_name).display().to_string()
+		}
+	}
 
-		s
-		/* file goes out of scope, and file_name gets closed */
+	/* Reads a whole UTF-8 file, or stdin for `-` */
+	pub fn read_in_file(file_name: &str) -> Result<String, FileError>
+	{
+		let mut bytes: Vec<u8> = Vec::new();
+		if file_name == STANDARD_STREAM {
+			io::stdin().lock().read_to_end(&mut bytes).map_err(|why| FileError::Read(file_name.to_string(), why))?;
+		} else {
+			/* Open the path in read-only mode, the file is closed when it goes out of scope */
+			let mut file = File::open(Path::new(file_name)).map_err(|why| FileError::Open(file_name.to_string(), why))?;
+			file.read_to_end(&mut bytes).map_err(|why| FileError::Read(file_name.to_string(), why))?;
+		}
+
+		String::from_utf8(bytes).map_err(|why| {
+			let offset = why.utf8_error().valid_up_to();
+			let line = why.as_bytes()[..offset].iter().filter(|&&byte| byte == b'\n').count() + 1;
+			FileError::NotUtf8(file_name.to_string(), line, offset)
+		})
+	}
+
+	/* Creates or truncates a file and writes `contents` to it, or to stdout for `-` */
+	pub fn write_out_file(file_name: &str, contents: &str) -> Result<(), FileError>
+	{
+		let written = if file_name == STANDARD_STREAM {
+			let stdout = io::stdout();
+			let mut handle = stdout.lock();
+			handle.write_all(contents.as_bytes()).and_then(|_| handle.flush())
+		} else {
+			let mut file = File::create(Path::new(file_name)).map_err(|why| FileError::Create(file_name.to_string(), why))?;
+			file.write_all(contents.as_bytes())
+		};
+		written.map_err(|why| FileError::Write(file_name.to_string(), why))
 	}
-}
\ No newline at end of file
+}
@@ -0,0 +1,380 @@
+/* Stack Virtual Machine, executes parsed jaz programs directly
+ * Variables live in activation records. `begin` opens a frame for the callee,
+ * `end` drops it, and which frame lvalue and rvalue refer to depends on how far
+ * the call has got: between begin and call lvalue is the callee's and rvalue
+ * the caller's, inside the callee both are the callee's, and between return
+ * and end rvalue is the callee's and lvalue the caller's. A call outside of a
+ * begin block shares the caller's frame.
+ */
+use std::collections::HashMap;
+use std::fmt;
+use std::io::Write;
+
+use diagnostics::Diagnostic;
+use parsetree::{Instruction, Program};
+
+/* Entries on the value stack: integers, or the address pushed by lvalue */
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum Value {
+	Integer(i64),
+	Address(usize, String),		/* Id of the frame the variable lives in, and its name */
+}
+
+/* How far the call a frame was opened for has got */
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+enum Phase {
+	Setup,		/* Between begin and call */
+	Active,		/* Running, also the program's own frame */
+	Returned,	/* Between return and end */
+}
+
+struct Frame {
+	id: usize,
+	phase: Phase,
+	store: HashMap<String, i64>,	/* Data store, variables start at zero */
+}
+
+impl Frame {
+	fn new(id: usize, phase: Phase) -> Frame {
+		Frame { id, phase, store: HashMap::new() }
+	}
+}
+
+/* What return needs to restore */
+struct Activation {
+	address: usize,		/* Instruction after the call */
+	frames: usize,		/* Number of frames when the call was made */
+	activated: bool,	/* Whether the call ran in a frame opened by begin */
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum RuntimeError {
+	UndefinedLabel(String),
+	StackUnderflow(usize),		/* Instruction index that popped an empty stack */
+	ExpectedInteger(usize),		/* An address was used as a number */
+	ExpectedAddress(usize),		/* := did not find an lvalue below the value */
+	DivisionByZero(usize),
+	ReturnWithoutCall(usize),
+	UnmatchedEnd(usize),		/* end with no begin block open */
+	UnclosedBegin(usize),		/* return with a begin block of the subroutine still open */
+	DanglingAddress(usize),		/* := into a frame that end has already dropped */
+	Output(String),				/* Writing to the output device failed */
+}
+
+impl fmt::Display for RuntimeError {
+	fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+		match *self {
+			RuntimeError::UndefinedLabel(ref label) => write!(f, "jump to undefined label `{}`", label),
+			RuntimeError::StackUnderflow(pc) => write!(f, "stack underflow at instruction {}", pc),
+			RuntimeError::ExpectedInteger(pc) => write!(f, "expected an integer on the stack at instruction {}", pc),
+			RuntimeError::ExpectedAddress(pc) => write!(f, "expected an lvalue on the stack at instruction {}", pc),
+			RuntimeError::DivisionByZero(pc) => write!(f, "division by zero at instruction {}", pc),
+			RuntimeError::ReturnWithoutCall(pc) => write!(f, "return without a matching call at instruction {}", pc),
+			RuntimeError::UnmatchedEnd(pc) => write!(f, "end without an open begin block at instruction {}", pc),
+			RuntimeError::UnclosedBegin(pc) => write!(f, "return with a begin block still open at instruction {}", pc),
+			RuntimeError::DanglingAddress(pc) => write!(f, "assignment to a dropped frame at instruction {}", pc),
+			RuntimeError::Output(ref why) => write!(f, "couldn't write output: {}", why),
+		}
+	}
+}
+
+impl RuntimeError {
+	pub fn diagnostic(&self, program: &Program) -> Diagnostic {
+		match *self {
+			RuntimeError::UndefinedLabel(ref label) => {
+				let diagnostic = Diagnostic::error(format!("jump to undefined label `{}`", label));
+				match program.code().iter().position(|instruction| instruction.jumps_to(label)) {
+					Some(index) => diagnostic.at(program.span(index)).label("no `label` with this name"),
+					None => diagnostic,
+				}
+			}
+			RuntimeError::StackUnderflow(pc) => Diagnostic::error("stack underflow").at(program.span(pc)).label("pops an empty stack"),
+			RuntimeError::ExpectedInteger(pc) => Diagnostic::error("expected an integer on the stack")
+				.at(program.span(pc))
+				.label("finds an lvalue here"),
+			RuntimeError::ExpectedAddress(pc) => Diagnostic::error("expected an lvalue on the stack")
+				.at(program.span(pc))
+				.label("nothing to assign to"),
+			RuntimeError::DivisionByZero(pc) => Diagnostic::error("division by zero").at(program.span(pc)).label("divisor is zero"),
+			RuntimeError::ReturnWithoutCall(pc) => Diagnostic::error("return without a matching call").at(program.span(pc)),
+			RuntimeError::UnmatchedEnd(pc) => Diagnostic::error("end without an open begin block")
+				.at(program.span(pc))
+				.label("no frame to drop")
+				.note("control reached this `end` without passing its `begin`"),
+			RuntimeError::UnclosedBegin(pc) => Diagnostic::error("return with a begin block still open")
+				.at(program.span(pc))
+				.help("close every `begin` with its `end` before returning"),
+			RuntimeError::DanglingAddress(pc) => Diagnostic::error("assignment to a variable whose frame was dropped")
+				.at(program.span(pc))
+				.note("the `lvalue` was pushed inside a begin block that has ended since"),
+			RuntimeError::Output(ref why) => Diagnostic::error(format!("couldn't write output: {}", why)),
+		}
+	}
+}
+
+pub struct Interpreter<'a> {
+	program: &'a Program,
+	frames: Vec<Frame>,				/* Activation records, the program's own frame first */
+	next_frame: usize,				/* Id for the next frame begin opens */
+	stack: Vec<Value>,				/* Value stack */
+	calls: Vec<Activation>,			/* Call stack */
+	pc: usize,
+}
+
+impl<'a> Interpreter<'a> {
+	pub fn new(program: &'a Program) -> Interpreter<'a> {
+		Interpreter {
+			program,
+			frames: vec![Frame::new(0, Phase::Active)],
+			next_frame: 1,
+			stack: Vec::new(),
+			calls: Vec::new(),
+			pc: 0,
+		}
+	}
+
+	pub fn run<W: Write>(&mut self, out: &mut W) -> Result<(), RuntimeError> {
+		let code = self.program.code();
+		while self.pc < code.len() {
+			let pc = self.pc;
+			self.pc += 1;
+
+			match code[pc].clone() {
+				Instruction::Push(constant) => self.stack.push(Value::Integer(constant)),
+				Instruction::Rvalue(name) => {
+					let frame = self.scope(false);
+					let value = *self.frames[frame].store.get(&name).unwrap_or(&0);
+					self.stack.push(Value::Integer(value));
+				}
+				Instruction::Lvalue(name) => {
+					let frame = self.scope(true);
+					self.stack.push(Value::Address(self.frames[frame].id, name));
+				}
+				Instruction::Pop => {
+					self.pop(pc)?;
+				}
+				Instruction::Assign => {
+					let value = self.pop_integer(pc)?;
+					match self.pop(pc)? {
+						Value::Address(id, name) => {
+							let frame = self.frames.iter_mut().rev().find(|frame| frame.id == id);
+							frame.ok_or(RuntimeError::DanglingAddress(pc))?.store.insert(name, value);
+						}
+						Value::Integer(_) => return Err(RuntimeError::ExpectedAddress(pc)),
+					}
+				}
+				Instruction::Copy => {
+					let top = match self.stack.last() {
+						Some(top) => top.clone(),
+						None => return Err(RuntimeError::StackUnderflow(pc)),
+					};
+					self.stack.push(top);
+				}
+				Instruction::Label(_) => { /* Marker only */ }
+				Instruction::Begin => {
+					self.frames.push(Frame::new(self.next_frame, Phase::Setup));
+					self.next_frame += 1;
+				}
+				Instruction::End => {
+					/* The program's own frame and running callees' frames are never dropped */
+					if self.frames.last().map(|frame| frame.phase) == Some(Phase::Active) {
+						return Err(RuntimeError::UnmatchedEnd(pc));
+					}
+					self.frames.pop();
+				}
+				Instruction::Goto(label) => self.pc = self.target(&label)?,
+				Instruction::GoFalse(label) => {
+					if self.pop_integer(pc)? == 0 {
+						self.pc = self.target(&label)?;
+					}
+				}
+				Instruction::GoTrue(label) => {
+					if self.pop_integer(pc)? != 0 {
+						self.pc = self.target(&label)?;
+					}
+				}
+				Instruction::Halt => break,
+				Instruction::Not => {
+					let value = self.pop_integer(pc)?;
+					self.stack.push(Value::Integer((value == 0) as i64));
+				}
+				Instruction::Print => {
+					let value = match self.stack.last() {
+						Some(&Value::Integer(value)) => value,
+						Some(&Value::Address(..)) => return Err(RuntimeError::ExpectedInteger(pc)),
+						None => return Err(RuntimeError::StackUnderflow(pc)),
+					};
+					writeln!(out, "{}", value).map_err(|why| RuntimeError::Output(why.to_string()))?;
+				}
+				Instruction::Show(text) => {
+					writeln!(out, "{}", text).map_err(|why| RuntimeError::Output(why.to_string()))?;
+				}
+				Instruction::Call(label) => {
+					let target = self.target(&label)?;
+					let frame = self.frames.last_mut().unwrap();
+					let activated = frame.phase != Phase::Active;
+					frame.phase = Phase::Active;
+					self.calls.push(Activation { address: self.pc, frames: self.frames.len(), activated });
+					self.pc = target;
+				}
+				Instruction::Return => {
+					let activation = self.calls.pop().ok_or(RuntimeError::ReturnWithoutCall(pc))?;
+					if self.frames.len() != activation.frames {
+						return Err(RuntimeError::UnclosedBegin(pc));
+					}
+					if activation.activated {
+						self.frames.last_mut().unwrap().phase = Phase::Returned;
+					}
+					self.pc = activation.address;
+				}
+				ref binary => {
+					/* Top of stack is the right hand operand */
+					let right = self.pop_integer(pc)?;
+					let left = self.pop_integer(pc)?;
+					let result = binary.evaluate(left, right).ok_or(RuntimeError::DivisionByZero(pc))?;
+					self.stack.push(Value::Integer(result));
+				}
+			}
+		}
+
+		out.flush().map_err(|why| RuntimeError::Output(why.to_string()))
+	}
+
+	/* Index of the frame lvalue (or rvalue) refers to right now */
+	fn scope(&self, lvalue: bool) -> usize {
+		let top = self.frames.len() - 1;
+		match self.frames[top].phase {
+			Phase::Active => top,
+			Phase::Setup => if lvalue { top } else { top - 1 },
+			Phase::Returned => if lvalue { top - 1 } else { top },
+		}
+	}
+
+	fn target(&self, label: &str) -> Result<usize, RuntimeError> {
+		self.program.target(label).ok_or_else(|| RuntimeError::UndefinedLabel(label.to_string()))
+	}
+
+	fn pop(&mut self, pc: usize) -> Result<Value, RuntimeError> {
+		self.stack.pop().ok_or(RuntimeError::StackUnderflow(pc))
+	}
+
+	fn pop_integer(&mut self, pc: usize) -> Result<i64, RuntimeError> {
+		match self.pop(pc)? {
+			Value::Integer(value) => Ok(value),
+			Value::Address(..) => Err(RuntimeError::ExpectedInteger(pc)),
+		}
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::{Interpreter, RuntimeError};
+
+	/* What the program prints, and how it ends */
+	fn run(source: &str) -> (String, Result<(), RuntimeError>) {
+		let program = ::parse(source).unwrap();
+		let mut out: Vec<u8> = Vec::new();
+		let result = Interpreter::new(&program).run(&mut out);
+		(String::from_utf8(out).unwrap(), result)
+	}
+
+	fn output(source: &str) -> String {
+		let (output, result) = run(source);
+		assert_eq!(result, Ok(()), "after printing:\n{}", output);
+		output
+	}
+
+	#[test]
+	fn runs_the_samples() {
+		assert!(output(include_str!("../factProc.jaz")).ends_with("5 factorial is:\n120\n\n"));
+		assert!(output(include_str!("../demo.jaz")).contains("after function work:\nvalue of x is:\n1\nvalue of f is:\n6\n"));
+		let foo = output(include_str!("../foo.jaz"));
+		assert!(foo.contains("in foo r is local.\ntherefore  r is:  \n0\n"));
+		assert!(foo.contains("value of p in caller function is:\n0\n"));
+
+		/* Every `a op b = r` line is followed by what the program computed */
+		let operators = output(include_str!("../operatorsTest.jaz"));
+		let lines: Vec<&str> = operators.lines().collect();
+		let mut checked = 0;
+		for pair in lines.windows(2) {
+			if let Some(position) = pair[0].rfind(" = ") {
+				assert_eq!(pair[1], pair[0][position + 3..].trim_end(), "after `{}`", pair[0]);
+				checked += 1;
+			}
+		}
+		assert!(checked >= 10);
+	}
+
+	#[test]
+	fn reports_stack_underflow() {
+		assert_eq!(run("pop\n").1, Err(RuntimeError::StackUnderflow(0)));
+		assert_eq!(run("push 1\n+\n").1, Err(RuntimeError::StackUnderflow(1)));
+		assert_eq!(run("copy\n").1, Err(RuntimeError::StackUnderflow(0)));
+		assert_eq!(run("print\n").1, Err(RuntimeError::StackUnderflow(0)));
+	}
+
+	#[test]
+	fn checks_values_and_addresses() {
+		assert_eq!(run("lvalue x\nprint\n").1, Err(RuntimeError::ExpectedInteger(1)));
+		assert_eq!(run("push 1\npush 2\n:=\n").1, Err(RuntimeError::ExpectedAddress(2)));
+		assert_eq!(output("lvalue x\ncopy\npush 4\n:=\npush 5\n:=\nrvalue x\nprint\n"), "5\n");
+	}
+
+	#[test]
+	fn stops_at_division_by_zero() {
+		let (output, result) = run("show before\npush 1\npush 0\n/\nshow after\n");
+		assert_eq!(output, "before\n");
+		assert_eq!(result, Err(RuntimeError::DivisionByZero(3)));
+		assert_eq!(run("push 1\npush 0\ndiv\n").1, Err(RuntimeError::DivisionByZero(2)));
+		assert_eq!(::codegen::RUNTIME_ERROR_STATUS, 3);
+	}
+
+	#[test]
+	fn wraps_on_overflow() {
+		assert_eq!(output("push 9223372036854775807\npush 1\n+\nprint\n"), "-9223372036854775808\n");
+		assert_eq!(output("push 0\npush 9223372036854775807\n-\npush 2\n-\nprint\n"), "9223372036854775807\n");
+		assert_eq!(output("push 4611686018427387904\npush 4\n*\nprint\n"), "0\n");
+	}
+
+	#[test]
+	fn divides_the_most_negative_value_by_minus_one() {
+		let min = "push 0\npush 9223372036854775807\n-\npush 1\n-\n";
+		let minus_one = "push 0\npush 1\n-\n";
+		assert_eq!(output(&format!("{}{}/\nprint\n", min, minus_one)), "-9223372036854775808\n");
+		assert_eq!(output(&format!("{}{}div\nprint\n", min, minus_one)), "0\n");
+		/* Division truncates towards zero, the remainder takes the sign of the dividend */
+		assert_eq!(output("push 0\npush 7\n-\npush 2\n/\nprint\npop\npush 0\npush 7\n-\npush 2\ndiv\nprint\n"), "-3\n-1\n");
+	}
+
+	#[test]
+	fn scopes_frames_across_calls() {
+		/* Before the call lvalue is the callee's and rvalue the caller's, after it the other way round */
+		let source = "lvalue x\npush 1\n:=\n\
+			begin\nlvalue x\nrvalue x\npush 10\n+\n:=\ncall f\nlvalue y\nrvalue x\n:=\nend\n\
+			rvalue y\nprint\npop\nrvalue x\nprint\npop\nhalt\n\
+			label f\nrvalue x\nprint\npop\nlvalue x\npush 7\n:=\nreturn\n";
+		assert_eq!(output(source), "11\n7\n1\n");
+
+		/* Without begin a subroutine shares its caller's frame */
+		assert_eq!(output("lvalue x\npush 1\n:=\ncall f\nrvalue x\nprint\nhalt\nlabel f\nlvalue x\npush 2\n:=\nreturn\n"), "2\n");
+
+		/* Every begin opens a fresh frame, whose variables start at zero */
+		let source = "begin\nlvalue x\npush 3\n:=\ncall f\nend\nbegin\ncall f\nend\nhalt\nlabel f\nrvalue x\nprint\npop\nreturn\n";
+		assert_eq!(output(source), "3\n0\n");
+
+		/* A recursive call gets its own frame too */
+		let source = "begin\nlvalue n\npush 3\n:=\ncall count\nend\nhalt\n\
+			label count\nrvalue n\nprint\ngofalse done\nbegin\nlvalue n\nrvalue n\npush 1\n-\n:=\ncall count\nend\nrvalue n\nprint\npop\nlabel done\nreturn\n";
+		assert_eq!(output(source), "3\n2\n1\n0\n1\n2\n3\n");
+	}
+
+	#[test]
+	fn rejects_unbalanced_calls() {
+		/* The parser pairs begin with end, only jumps can get around that */
+		assert_eq!(run("goto e\nbegin\nlabel e\nend\n").1, Err(RuntimeError::UnmatchedEnd(3)));
+		assert_eq!(run("return\n").1, Err(RuntimeError::ReturnWithoutCall(0)));
+		assert_eq!(run("call f\nhalt\nlabel f\nbegin\ngoto r\nend\nlabel r\nreturn\n").1, Err(RuntimeError::UnclosedBegin(7)));
+		assert_eq!(run("begin\nlvalue x\nend\npush 1\n:=\n").1, Err(RuntimeError::DanglingAddress(4)));
+		assert_eq!(run("goto nowhere\n").1, Err(RuntimeError::UndefinedLabel("nowhere".to_string())));
+	}
+}
@@ -0,0 +1,96 @@
+/* jaz toolchain
+ * Lexer, parser, static analyses, code generators and stack machine for the
+ * jaz language (the specification is in main.rs). The functions below run the
+ * usual pipeline and report problems as Diagnostics; the modules stay public
+ * for callers that want the typed errors or a single stage.
+ *
+ *	let program = jaz::parse(source)?;
+ *	let diagnostics = jaz::analyse(&program);
+ *	if !diagnostics.iter().any(|diagnostic| diagnostic.is_error()) {
+ *		let cpp = jaz::generate(&jaz::optimize(&program), Target::Cpp)?;
+ *	}
+ */
+pub mod tokenizer;
+pub mod fileio;
+pub mod parsetree;
+pub mod interpreter;
+pub mod codegen;
+pub mod diagnostics;
+pub mod analysis;
+pub mod optimizer;
+
+use std::io::Write;
+
+use analysis::labels::LabelError;
+use analysis::reachability::ReachError;
+
+pub use codegen::Target;
+pub use diagnostics::{Diagnostic, Renderer, Severity};
+pub use interpreter::Interpreter;
+pub use parsetree::{Instruction, Parser, Program};
+pub use tokenizer::{Span, Spanned, Token, Tokenizer};
+
+/* Splits jaz source into tokens */
+pub fn lex(source: &str) -> Result<Vec<Spanned<Token>>, Vec<Diagnostic>> {
+	Tokenizer::lex(source).map_err(|errors| errors.iter().map(|why| why.diagnostic()).collect())
+}
+
+/* Lexes and parses jaz source into a program */
+pub fn parse(source: &str) -> Result<Program, Vec<Diagnostic>> {
+	let tokens = lex(source)?;
+	Parser::parse(&tokens).map_err(|why| vec![why.diagnostic()])
+}
+
+/* Runs every static check, errors and warnings come back in the order the checks ran */
+pub fn analyse(program: &Program) -> Vec<Diagnostic> {
+	let (table, labels) = analysis::labels::resolve(program);
+	let stack = analysis::stack::verify(program);
+	/* Jumps to labels that do not resolve go nowhere, so what they miss is not dead code */
+	let resolved = !labels.iter().any(|problem| problem.is_error());
+	let reachability = if resolved {
+		analysis::reachability::check(program, &table)
+	} else {
+		Vec::new()
+	};
+	/* Which variable an assignment stores to is only known when the stack is used correctly */
+	let initialization = if resolved && !stack.iter().any(|problem| problem.is_error()) {
+		analysis::initialization::check(program)
+	} else {
+		Vec::new()
+	};
+
+	/* An uncalled subroutine is reported once, as a subroutine rather than as an unused label */
+	let subroutines: Vec<usize> = reachability.iter().filter_map(|problem| match *problem {
+		ReachError::UnusedSubroutine(_, index, _, _) => Some(index),
+		_ => None,
+	}).collect();
+	labels.iter()
+		.filter(|problem| !matches!(**problem, LabelError::Unused(_, index) if subroutines.contains(&index)))
+		.map(|problem| problem.diagnostic(program))
+		.chain(stack.iter().map(|problem| problem.diagnostic(program)))
+		.chain(reachability.iter().map(|problem| problem.diagnostic(program)))
+		.chain(initialization.iter().map(|problem| problem.diagnostic(program)))
+		.collect()
+}
+
+/* Folds constants and removes instructions that cancel out or never run, see optimizer */
+pub fn optimize(program: &Program) -> Program {
+	optimizer::optimize(program)
+}
+
+/* Lowers a program the way every backend does, without writing any code. Besides the
+ * stack use `analyse` checks, this rejects paths that meet inside different begin blocks
+ */
+pub fn lower(program: &Program) -> Result<(), Vec<Diagnostic>> {
+	codegen::lower(program).map(|_| ()).map_err(|why| vec![why.diagnostic(program)])
+}
+
+/* Generates code for a program, which should have passed `analyse` without errors */
+pub fn generate(program: &Program, target: Target) -> Result<String, Vec<Diagnostic>> {
+	codegen::generate(program, target).map_err(|why| vec![why.diagnostic(program)])
+}
+
+/* Executes a program on the stack machine, writing what it prints to `out` */
+pub fn run<W: Write>(program: &Program, out: &mut W) -> Result<(), Vec<Diagnostic>> {
+	Interpreter::new(program).run(out).map_err(|why| vec![why.diagnostic(program)])
+}
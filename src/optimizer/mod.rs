@@ -0,0 +1,244 @@
+/* Optimizer
+ * Rewrites the instructions of a program into fewer that behave the same:
+ * constant folding, algebraic identities, stack traffic that cancels out and
+ * branches on constants. `show` is moved ahead of pushes, which have no side
+ * effects, so text between operands does not keep them apart. Every rewrite
+ * replaces a short run of instructions none of which is a label, so no jump
+ * can land inside what it replaces, and rewrites repeat until none applies.
+ * Code that never runs and labels nothing jumps to are taken out in between,
+ * since branches on constants leave both behind and both keep rewrites apart.
+ * The rewrites assume the stack is used correctly, so programs the stack
+ * verifier rejects are left as they are.
+ */
+use std::collections::HashSet;
+
+use analysis::cfg::Cfg;
+use analysis::{labels, reachability, stack};
+use parsetree::{Instruction, Program};
+use tokenizer::Span;
+
+/* The program with every rewrite applied, or unchanged when it does not verify */
+pub fn optimize(program: &Program) -> Program {
+	let (_, label_problems) = labels::resolve(program);
+	let stack_problems = stack::verify(program);
+	if label_problems.iter().any(|problem| problem.is_error()) || stack_problems.iter().any(|problem| problem.is_error()) {
+		return program.clone();
+	}
+
+	/* Labels and begin blocks are only ever taken out whole, so every rebuild succeeds */
+	let mut program = program.clone();
+	loop {
+		let rewritten = match rebuild(peephole(entries(&program))) {
+			Some(rewritten) => rewritten,
+			None => return program,
+		};
+		let stripped = match rebuild(strip(&rewritten)) {
+			Some(stripped) => stripped,
+			None => return rewritten,
+		};
+		if stripped.code() == program.code() {
+			return stripped;
+		}
+		program = stripped;
+	}
+}
+
+/* Every instruction with its source position */
+fn entries(program: &Program) -> Vec<(Instruction, Span)> {
+	program.code().iter().cloned().zip(program.spans().iter().cloned()).collect()
+}
+
+fn rebuild(code: Vec<(Instruction, Span)>) -> Option<Program> {
+	let (code, spans): (Vec<Instruction>, Vec<Span>) = code.into_iter().unzip();
+	Program::new(code, spans).ok()
+}
+
+/* Applies the rewrites until none does */
+fn peephole(mut code: Vec<(Instruction, Span)>) -> Vec<(Instruction, Span)> {
+	loop {
+		let mut rewritten: Vec<(Instruction, Span)> = Vec::with_capacity(code.len());
+		let mut changed = false;
+		let mut index = 0;
+		while index < code.len() {
+			let window: Vec<&Instruction> = code[index..].iter().take(3).map(|(instruction, _)| instruction).collect();
+			match rewrite(&window) {
+				Some((consumed, replacement)) => {
+					rewritten.extend(replacement.into_iter().map(|(instruction, from)| (instruction, code[index + from].1)));
+					index += consumed;
+					changed = true;
+				}
+				None => {
+					rewritten.push(code[index].clone());
+					index += 1;
+				}
+			}
+		}
+		code = rewritten;
+		if !changed {
+			return code;
+		}
+	}
+}
+
+/* Drops the instructions that never run, keeping a begin or end whose partner runs,
+ * and then the labels nothing jumps to or calls any more.
+ */
+fn strip(program: &Program) -> Vec<(Instruction, Span)> {
+	let mut keep = reachability::reachable(program, &Cfg::build(program));
+
+	let mut open: Vec<usize> = Vec::new();
+	for (index, instruction) in program.code().iter().enumerate() {
+		match *instruction {
+			Instruction::Begin => open.push(index),
+			Instruction::End => {
+				if let Some(begin) = open.pop() {
+					let either = keep[begin] || keep[index];
+					keep[begin] = either;
+					keep[index] = either;
+				}
+			}
+			_ => {}
+		}
+	}
+
+	let live: Vec<(Instruction, Span)> = entries(program).into_iter().zip(keep).filter(|&(_, keep)| keep).map(|(entry, _)| entry).collect();
+	let targets: HashSet<String> = live.iter().filter_map(|(instruction, _)| match *instruction {
+		Instruction::Goto(ref label) | Instruction::GoFalse(ref label) | Instruction::GoTrue(ref label) | Instruction::Call(ref label) => {
+			Some(label.clone())
+		}
+		_ => None,
+	}).collect();
+	live.into_iter().filter(|(instruction, _)| match *instruction {
+		Instruction::Label(ref label) => targets.contains(label),
+		_ => true,
+	}).collect()
+}
+
+/* A rewrite of the instructions starting the window, as how many it replaces and by
+ * what. Each new instruction keeps the source position of one in the window.
+ */
+fn rewrite(window: &[&Instruction]) -> Option<(usize, Vec<(Instruction, usize)>)> {
+	let at_start = |(consumed, replacement): (usize, Vec<Instruction>)| {
+		(consumed, replacement.into_iter().map(|instruction| (instruction, 0)).collect())
+	};
+	if let [&Instruction::Push(_), &Instruction::Show(_), ..] | [&Instruction::Rvalue(_), &Instruction::Show(_), ..] = *window {
+		return Some((2, vec![(window[1].clone(), 1), (window[0].clone(), 0)]));
+	}
+	let rewritten = match *window {
+		/* Constant folding, division by zero is left to fail when it runs */
+		[&Instruction::Push(left), &Instruction::Push(right), operator, ..] if operator.is_binary() => {
+			operator.evaluate(left, right).map(|result| (3, vec![Instruction::Push(result)]))
+		}
+		[&Instruction::Push(value), &Instruction::Not, ..] => Some((2, vec![Instruction::Push((value == 0) as i64)])),
+
+		/* x + 0, x - 0, x * 1 and x / 1 are x, x * 0 is 0 */
+		[&Instruction::Push(0), &Instruction::Add, ..] | [&Instruction::Push(0), &Instruction::Sub, ..] => Some((2, Vec::new())),
+		[&Instruction::Push(1), &Instruction::Mul, ..] | [&Instruction::Push(1), &Instruction::Div, ..] => Some((2, Vec::new())),
+		[&Instruction::Push(0), &Instruction::Mul, ..] => Some((2, vec![Instruction::Pop, Instruction::Push(0)])),
+
+		/* Values pushed only to be popped */
+		[&Instruction::Copy, &Instruction::Pop, ..]
+		| [&Instruction::Push(_), &Instruction::Pop, ..]
+		| [&Instruction::Rvalue(_), &Instruction::Pop, ..] => Some((2, Vec::new())),
+
+		/* Branches on constants always or never jump */
+		[&Instruction::Push(value), Instruction::GoFalse(label), ..] => {
+			Some((2, if value == 0 { vec![Instruction::Goto(label.clone())] } else { Vec::new() }))
+		}
+		[&Instruction::Push(value), Instruction::GoTrue(label), ..] => {
+			Some((2, if value != 0 { vec![Instruction::Goto(label.clone())] } else { Vec::new() }))
+		}
+
+		/* Jumps to the next instruction */
+		[Instruction::Goto(target), Instruction::Label(label), ..] if target == label => Some((1, Vec::new())),
+		_ => None,
+	};
+	rewritten.map(at_start)
+}
+
+#[cfg(test)]
+mod tests {
+	use super::optimize;
+	use interpreter::{Interpreter, RuntimeError};
+	use parsetree::{Instruction, Program};
+
+	fn program(source: &str) -> Program {
+		::parse(source).unwrap()
+	}
+
+	/* What running the program prints, and how it ends */
+	fn run(program: &Program) -> (String, Result<(), RuntimeError>) {
+		let mut out: Vec<u8> = Vec::new();
+		let result = Interpreter::new(program).run(&mut out);
+		(String::from_utf8(out).unwrap(), result)
+	}
+
+	/* Optimizes the program, checking -O1 behaves exactly as -O0 does */
+	fn same(source: &str) -> Program {
+		let original = program(source);
+		let optimized = optimize(&original);
+		assert_eq!(run(&optimized), run(&original), "optimized into:\n{:?}", optimized.code());
+		optimized
+	}
+
+	#[test]
+	fn folds_constants() {
+		let optimized = same("push 2\npush 3\n+\npush 4\n*\n!\n!\nprint\nhalt\n");
+		assert_eq!(optimized.code(), &[Instruction::Push(1), Instruction::Print, Instruction::Halt]);
+		/* Folding wraps as running does, the most negative value divided by -1 stays as it is */
+		let optimized = same("push 0\npush 9223372036854775807\n-\npush 1\n-\npush 0\npush 1\n-\n/\nprint\nhalt\n");
+		assert_eq!(optimized.code()[0], Instruction::Push(i64::MIN));
+	}
+
+	#[test]
+	fn keeps_division_by_zero() {
+		let optimized = same("push 7\npush 0\n/\nprint\nhalt\n");
+		assert!(optimized.code().contains(&Instruction::Div));
+		assert_eq!(run(&optimized).1, Err(RuntimeError::DivisionByZero(2)));
+	}
+
+	#[test]
+	fn multiplying_by_zero_still_divides() {
+		let optimized = same("show before\npush 1\npush 0\n/\npush 0\n*\nprint\nshow after\nhalt\n");
+		assert!(optimized.code().contains(&Instruction::Div));
+		let (output, result) = run(&optimized);
+		assert_eq!(output, "before\n");
+		assert!(result.is_err());
+
+		let optimized = same("lvalue x\npush 5\n:=\nrvalue x\npush 0\n*\nprint\nhalt\n");
+		assert!(!optimized.code().contains(&Instruction::Mul));
+	}
+
+	#[test]
+	fn moves_show_ahead_of_pushes() {
+		let optimized = same("push 1\nshow between\npush 2\n+\nprint\nhalt\n");
+		assert_eq!(optimized.code()[0], Instruction::Show("between".to_string()));
+		assert_eq!(optimized.code()[1], Instruction::Push(3));
+		same("lvalue x\npush 4\n:=\nrvalue x\nshow x is\nprint\nhalt\n");
+	}
+
+	#[test]
+	fn resolves_constant_branches() {
+		let optimized = same("push 0\ngofalse yes\nshow no\nlabel yes\nshow yes\npush 1\ngotrue done\nshow never\nlabel done\nhalt\n");
+		assert_eq!(optimized.code(), &[Instruction::Show("yes".to_string()), Instruction::Halt]);
+		let optimized = same("push 3\ngofalse skip\nshow taken\nlabel skip\nhalt\n");
+		assert!(optimized.code().contains(&Instruction::Show("taken".to_string())));
+	}
+
+	#[test]
+	fn strips_unreachable_code() {
+		let optimized = same("goto over\nshow dead\nlvalue x\npush 1\n:=\nlabel over\nshow live\nhalt\nshow after halt\n");
+		assert_eq!(optimized.code(), &[Instruction::Show("live".to_string()), Instruction::Halt]);
+	}
+
+	#[test]
+	fn keeps_subroutines() {
+		same("lvalue n\npush 3\n:=\ncall count\nhalt\nlabel count\nrvalue n\nprint\npop\nlvalue n\nrvalue n\npush 1\n-\n:=\nrvalue n\ngotrue count\nreturn\n");
+	}
+
+	#[test]
+	fn leaves_rejected_programs_alone() {
+		let original = program("pop\nhalt\n");
+		assert_eq!(optimize(&original).code(), original.code());
+	}
+}
@@ -1,14 +1,393 @@
 /* Parse Tree */
+use std::collections::HashMap;
+use std::fmt;
+
+use diagnostics::Diagnostic;
+use tokenizer::{Span, Spanned, Token};
+
+#[derive(Clone)]
 pub struct Node <T> {
-	child: Vec<T>,
+	child: Vec<Node<T>>,
 	entry: T
 }
 
 impl <T> Node <T> {
 	pub fn new(entry: T) -> Node <T> {
-		return Node {
+		Node {
 			child: Vec::new(),
-			entry: entry
+			entry
+		}
+	}
+
+	pub fn add_child(&mut self, child: Node<T>) {
+		self.child.push(child);
+	}
+
+	pub fn entry(&self) -> &T {
+		&self.entry
+	}
+
+	pub fn children(&self) -> &[Node<T>] {
+		&self.child
+	}
+
+	/* Pre-order traversal, the callback also receives the depth of the node */
+	pub fn walk<F: FnMut(&T, usize)>(&self, visit: &mut F) {
+		self.walk_at(0, visit);
+	}
+
+	fn walk_at<F: FnMut(&T, usize)>(&self, depth: usize, visit: &mut F) {
+		visit(&self.entry, depth);
+		for child in &self.child {
+			child.walk_at(depth + 1, visit);
+		}
+	}
+}
+
+/* One variant per jaz opcode, see the language specification in main.rs */
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum Instruction {
+	Push(i64),				/* push c */
+	Rvalue(String),			/* rvalue l */
+	Lvalue(String),			/* lvalue l */
+	Pop,					/* pop */
+	Assign,					/* := */
+	Copy,					/* copy */
+	Label(String),			/* label l, numeric labels keep their digits */
+	Goto(String),			/* goto l */
+	GoFalse(String),		/* gofalse l */
+	GoTrue(String),			/* gotrue l */
+	Halt,					/* halt */
+	Add,					/* + */
+	Sub,					/* - */
+	Mul,					/* * */
+	Div,					/* / */
+	Rem,					/* div */
+	And,					/* & */
+	Not,					/* ! */
+	Or,						/* | */
+	NotEqual,				/* <> */
+	LessEqual,				/* <= */
+	GreaterEqual,			/* >= */
+	Less,					/* < */
+	Greater,				/* > */
+	Equal,					/* = */
+	Print,					/* print */
+	Show(String),			/* show text */
+	Begin,					/* begin */
+	End,					/* end */
+	Return,					/* return */
+	Call(String),			/* call l */
+}
+
+impl Instruction {
+	/* Whether this is a goto, conditional jump or call to `label` */
+	pub fn jumps_to(&self, label: &str) -> bool {
+		match *self {
+			Instruction::Goto(ref target)
+			| Instruction::GoFalse(ref target)
+			| Instruction::GoTrue(ref target)
+			| Instruction::Call(ref target) => target == label,
+			_ => false,
+		}
+	}
+
+	/* Arithmetic, logic and comparison operators, which pop two values and push one */
+	pub fn is_binary(&self) -> bool {
+		self.evaluate(0, 1).is_some()
+	}
+
+	/* What a binary operator pushes for its operands, None when it divides by zero or is not one.
+	 * Arithmetic wraps around on overflow, logic and comparisons push 0 or 1.
+	 */
+	pub fn evaluate(&self, left: i64, right: i64) -> Option<i64> {
+		match *self {
+			Instruction::Add => Some(left.wrapping_add(right)),
+			Instruction::Sub => Some(left.wrapping_sub(right)),
+			Instruction::Mul => Some(left.wrapping_mul(right)),
+			Instruction::Div | Instruction::Rem if right == 0 => None,
+			Instruction::Div => Some(left.wrapping_div(right)),
+			Instruction::Rem => Some(left.wrapping_rem(right)),
+			Instruction::And => Some((left != 0 && right != 0) as i64),
+			Instruction::Or => Some((left != 0 || right != 0) as i64),
+			Instruction::NotEqual => Some((left != right) as i64),
+			Instruction::LessEqual => Some((left <= right) as i64),
+			Instruction::GreaterEqual => Some((left >= right) as i64),
+			Instruction::Less => Some((left < right) as i64),
+			Instruction::Greater => Some((left > right) as i64),
+			Instruction::Equal => Some((left == right) as i64),
+			_ => None,
+		}
+	}
+}
+
+impl fmt::Display for Instruction {
+	/* Renders the instruction back into jaz source form */
+	fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+		match *self {
+			Instruction::Push(constant) => write!(f, "push {}", constant),
+			Instruction::Rvalue(ref name) => write!(f, "rvalue {}", name),
+			Instruction::Lvalue(ref name) => write!(f, "lvalue {}", name),
+			Instruction::Pop => write!(f, "pop"),
+			Instruction::Assign => write!(f, ":="),
+			Instruction::Copy => write!(f, "copy"),
+			Instruction::Label(ref label) => write!(f, "label {}", label),
+			Instruction::Goto(ref label) => write!(f, "goto {}", label),
+			Instruction::GoFalse(ref label) => write!(f, "gofalse {}", label),
+			Instruction::GoTrue(ref label) => write!(f, "gotrue {}", label),
+			Instruction::Halt => write!(f, "halt"),
+			Instruction::Add => write!(f, "+"),
+			Instruction::Sub => write!(f, "-"),
+			Instruction::Mul => write!(f, "*"),
+			Instruction::Div => write!(f, "/"),
+			Instruction::Rem => write!(f, "div"),
+			Instruction::And => write!(f, "&"),
+			Instruction::Not => write!(f, "!"),
+			Instruction::Or => write!(f, "|"),
+			Instruction::NotEqual => write!(f, "<>"),
+			Instruction::LessEqual => write!(f, "<="),
+			Instruction::GreaterEqual => write!(f, ">="),
+			Instruction::Less => write!(f, "<"),
+			Instruction::Greater => write!(f, ">"),
+			Instruction::Equal => write!(f, "="),
+			Instruction::Print => write!(f, "print"),
+			Instruction::Show(ref text) => write!(f, "show {}", text),
+			Instruction::Begin => write!(f, "begin"),
+			Instruction::End => write!(f, "end"),
+			Instruction::Return => write!(f, "return"),
+			Instruction::Call(ref label) => write!(f, "call {}", label),
+		}
+	}
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum ParseError {
+	UnexpectedToken(Token, Span),		/* Token that does not start an instruction */
+	MissingOperand(String, Span),		/* Instruction is missing its operand */
+	UnmatchedBegin(Span),				/* begin without a closing end */
+	UnmatchedEnd(Span),					/* end without an opening begin */
+}
+
+impl ParseError {
+	pub fn span(&self) -> Span {
+		match *self {
+			ParseError::UnexpectedToken(_, span)
+			| ParseError::MissingOperand(_, span)
+			| ParseError::UnmatchedBegin(span)
+			| ParseError::UnmatchedEnd(span) => span,
+		}
+	}
+
+	fn message(&self) -> String {
+		match *self {
+			ParseError::UnexpectedToken(ref token, _) => format!("unexpected token {:?}", token),
+			ParseError::MissingOperand(ref keyword, _) => format!("`{}` is missing its operand", keyword),
+			ParseError::UnmatchedBegin(_) => "`begin` without a matching `end`".to_string(),
+			ParseError::UnmatchedEnd(_) => "`end` without a matching `begin`".to_string(),
 		}
 	}
-}
\ No newline at end of file
+
+	pub fn diagnostic(&self) -> Diagnostic {
+		let diagnostic = Diagnostic::error(self.message()).at(self.span());
+		match *self {
+			ParseError::UnexpectedToken(..) => diagnostic.label("expected an instruction"),
+			ParseError::MissingOperand(ref keyword, _) => {
+				let operand = match keyword.as_str() {
+					"push" => "a constant",
+					"rvalue" | "lvalue" => "a variable name",
+					"show" => "the text to print",
+					_ => "a label",
+				};
+				diagnostic.label(format!("expected {} after `{}`", operand, keyword))
+			}
+			ParseError::UnmatchedBegin(_) => diagnostic.label("opened here").note("every `begin` is closed by an `end` after its `call`"),
+			ParseError::UnmatchedEnd(_) => diagnostic.label("nothing to close"),
+		}
+	}
+}
+
+impl fmt::Display for ParseError {
+	fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+		write!(f, "{}: {}", self.span(), self.message())
+	}
+}
+
+/* A parsed jaz program.
+ * `code` is the flat instruction list every backend executes from, `spans`
+ * holds where each instruction came from in the source, `labels` maps label
+ * names to their index in it (the first definition, duplicates are reported by
+ * the label checks in analysis::labels), and `tree` nests each `begin … end` call block
+ * under its `begin` node (the closing `end` is the last child).
+ */
+#[derive(Clone)]
+pub struct Program {
+	code: Vec<Instruction>,
+	spans: Vec<Span>,
+	labels: HashMap<String, usize>,
+	tree: Vec<Node<Instruction>>,
+}
+
+impl Program {
+	pub fn new(code: Vec<Instruction>, spans: Vec<Span>) -> Result<Program, ParseError> {
+		assert_eq!(code.len(), spans.len(), "every instruction needs a span");
+
+		/* Resolve every label to the index of its instruction */
+		let mut labels: HashMap<String, usize> = HashMap::new();
+		for (index, instruction) in code.iter().enumerate() {
+			if let Instruction::Label(ref name) = *instruction {
+				labels.entry(name.clone()).or_insert(index);
+			}
+		}
+
+		/* Nest call blocks, `open` holds the begin nodes still waiting for their end */
+		let mut tree: Vec<Node<Instruction>> = Vec::new();
+		let mut open: Vec<(Node<Instruction>, Span)> = Vec::new();
+		for (instruction, &span) in code.iter().zip(&spans) {
+			match *instruction {
+				Instruction::Begin => open.push((Node::new(Instruction::Begin), span)),
+				Instruction::End => {
+					let (mut block, _) = open.pop().ok_or(ParseError::UnmatchedEnd(span))?;
+					block.add_child(Node::new(Instruction::End));
+					match open.last_mut() {
+						Some(&mut (ref mut parent, _)) => parent.add_child(block),
+						None => tree.push(block),
+					}
+				}
+				_ => {
+					let node = Node::new(instruction.clone());
+					match open.last_mut() {
+						Some(&mut (ref mut parent, _)) => parent.add_child(node),
+						None => tree.push(node),
+					}
+				}
+			}
+		}
+		if let Some(&(_, span)) = open.last() {
+			return Err(ParseError::UnmatchedBegin(span));
+		}
+
+		Ok(Program { code, spans, labels, tree })
+	}
+
+	pub fn code(&self) -> &[Instruction] {
+		&self.code
+	}
+
+	/* Source position of the instruction at `index` */
+	pub fn span(&self, index: usize) -> Span {
+		self.spans[index]
+	}
+
+	pub fn spans(&self) -> &[Span] {
+		&self.spans
+	}
+
+	pub fn labels(&self) -> &HashMap<String, usize> {
+		&self.labels
+	}
+
+	pub fn tree(&self) -> &[Node<Instruction>] {
+		&self.tree
+	}
+
+	/* Index of the instruction a jump or call to `label` continues at */
+	pub fn target(&self, label: &str) -> Option<usize> {
+		self.labels.get(label).cloned()
+	}
+}
+
+pub struct Parser;
+
+impl Parser {
+	/* Pair every keyword with the operand token the lexer placed after it */
+	pub fn parse(tokens: &[Spanned<Token>]) -> Result<Program, ParseError> {
+		let mut code: Vec<Instruction> = Vec::new();
+		let mut spans: Vec<Span> = Vec::new();
+		let mut iterable = tokens.iter().peekable();
+
+		while let Some(&Spanned { node: ref token, span }) = iterable.next() {
+			/* Operand tokens widen the instruction's span to cover them */
+			let mut end = span;
+			let mut operand = |keyword: &String| match iterable.next() {
+				Some(operand) => {
+					end = operand.span;
+					Ok(&operand.node)
+				}
+				None => Err(ParseError::MissingOperand(keyword.clone(), span)),
+			};
+			let instruction = match *token {
+				Token::Keyword(ref keyword) => {
+					match keyword.as_str() {
+						"push" => {
+							match operand(keyword)? {
+								&Token::Constant(constant) => Instruction::Push(constant),
+								_ => return Err(ParseError::MissingOperand(keyword.clone(), span)),
+							}
+						}
+						"rvalue" | "lvalue" => {
+							let name = match operand(keyword)? {
+								Token::Assignment(name) if name != ":=" => name.clone(),
+								_ => return Err(ParseError::MissingOperand(keyword.clone(), span)),
+							};
+							if keyword == "rvalue" { Instruction::Rvalue(name) } else { Instruction::Lvalue(name) }
+						}
+						"label" | "goto" | "gofalse" | "gotrue" | "call" => {
+							/* Labels are either numeric (label 2000) or named (label loop) */
+							let target = match operand(keyword)? {
+								&Token::Constant(constant) => constant.to_string(),
+								Token::FunctionName(name)
+								| Token::FunctionCall(name)
+								| Token::GotoLabel(name)
+								| Token::FunctionCallWithParams(name) => name.clone(),
+								_ => return Err(ParseError::MissingOperand(keyword.clone(), span)),
+							};
+							match keyword.as_str() {
+								"label" => Instruction::Label(target),
+								"goto" => Instruction::Goto(target),
+								"gofalse" => Instruction::GoFalse(target),
+								"gotrue" => Instruction::GoTrue(target),
+								_ => Instruction::Call(target),
+							}
+						}
+						"show" => {
+							match operand(keyword)? {
+								Token::Printable(text) => Instruction::Show(text.clone()),
+								_ => return Err(ParseError::MissingOperand(keyword.clone(), span)),
+							}
+						}
+						"pop" => Instruction::Pop,
+						"copy" => Instruction::Copy,
+						"halt" => Instruction::Halt,
+						"div" => Instruction::Rem,
+						"print" => Instruction::Print,
+						"begin" => Instruction::Begin,
+						"end" => Instruction::End,
+						"return" => Instruction::Return,
+						_ => return Err(ParseError::UnexpectedToken(token.clone(), span)),
+					}
+				}
+				Token::Assignment(ref assign) if assign == ":=" => Instruction::Assign,
+				Token::Plus => Instruction::Add,
+				Token::Minus => Instruction::Sub,
+				Token::Multiply => Instruction::Mul,
+				Token::IntDiv => Instruction::Div,
+				Token::RemainDiv(_) => Instruction::Rem,
+				Token::And => Instruction::And,
+				Token::Or => Instruction::Or,
+				Token::Not => Instruction::Not,
+				Token::Equivalent => Instruction::NotEqual,
+				Token::LessThanEqual => Instruction::LessEqual,
+				Token::GreaterThanEqual => Instruction::GreaterEqual,
+				Token::LessThan => Instruction::Less,
+				Token::GreaterThan => Instruction::Greater,
+				Token::Equal => Instruction::Equal,
+				Token::NewLine | Token::Whitespace(_) => continue,
+				_ => return Err(ParseError::UnexpectedToken(token.clone(), span)),
+			};
+			code.push(instruction);
+			spans.push(span.to(end));
+		}
+
+		Program::new(code, spans)
+	}
+}
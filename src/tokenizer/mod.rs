@@ -1,6 +1,11 @@
 /* Set of acceptible language tokens */
 use std::collections::HashSet;
-use std::ops::Deref;
+use std::fmt;
+use std::iter::Peekable;
+use std::str::CharIndices;
+
+use diagnostics::Diagnostic;
+
 pub struct Tokenizer;
 
 #[derive(Clone, Debug, PartialEq, Eq)]
@@ -32,109 +37,153 @@ pub enum Token {
 	RemainDiv(String),		/* div operator */
 }
 
+/* Where a token came from: byte offset and length, 1-based line and column */
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct Span {
+	pub offset: usize,
+	pub line: usize,
+	pub column: usize,
+	pub length: usize,
+}
+
+impl Span {
+	/* From the start of this span to the end of `end` */
+	pub fn to(&self, end: Span) -> Span {
+		Span {
+			offset: self.offset,
+			line: self.line,
+			column: self.column,
+			length: (end.offset + end.length).saturating_sub(self.offset),
+		}
+	}
+}
+
+impl fmt::Display for Span {
+	fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+		write!(f, "{}:{}", self.line, self.column)
+	}
+}
+
 #[derive(Clone, Debug, PartialEq, Eq)]
-pub struct Recognize {
-	token: Option<Token>,
-	to_match: bool,
-	collection: Vec<String>,
+pub enum LexErrorKind {
+	UnexpectedCharacter(char),
+	UnterminatedOperator(char),		/* ':' that is not followed by '=' */
+	IntegerOverflow(String),		/* Constant that does not fit in 64 bits */
+	UnknownKeyword(String),			/* Word that is neither a keyword nor an operand */
 }
 
-impl Tokenizer {
-	pub fn reduce_tokens_to_enum(raw: &mut Recognize, result: &mut Vec<Token>) {
-		if raw.to_match {
-			raw.to_match = false;
-
-			let a = raw.collection.iter().map(|a| a.to_string()).collect::<String>();
-			let token = raw.token.clone();
-
-			if a != "" {
-				match token.unwrap() {
-					Token::Printable(ref c) => {
-						let mut b = a.clone();
-						b.push_str(&" ".to_string());
-						result.push(Token::Printable(b.clone()));
-					}
-					Token::FunctionCall(ref c) => {
-						result.push(Token::FunctionCall(a.clone()));
-					}
-					Token::FunctionCallWithParams(ref c) => {
-						result.push(Token::FunctionCallWithParams(a.clone()));
-					}
-					Token::FunctionName(ref c) => {
-						result.push(Token::FunctionName(a.clone()));
-					}
-					Token::Assignment(ref c) => {
-						result.push(Token::Assignment(a.clone()));
-					}
-					Token::GotoLabel(ref c) => {
-						result.push(Token::GotoLabel(a.clone()));
-					}
-					_ => {/* Sink */}
-				}
-			}
-			raw.collection.clear()					
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct LexError {
+	pub kind: LexErrorKind,
+	pub span: Span,
+}
+
+impl LexError {
+	fn message(&self) -> String {
+		match self.kind {
+			LexErrorKind::UnexpectedCharacter(c) => format!("unexpected character {:?}", c),
+			LexErrorKind::UnterminatedOperator(c) => format!("unterminated operator `{}`, expected `{}=`", c, c),
+			LexErrorKind::IntegerOverflow(ref digits) => format!("constant {} does not fit in a 64-bit integer", digits),
+			LexErrorKind::UnknownKeyword(ref word) => format!("unknown keyword `{}`", word),
 		}
 	}
 
-	fn build_recognizer(
-		final_str: &str, 
-		old_token: Option<Token>, 
-		can_update_recognizer: &mut bool
-	) -> Recognize {
-		let token = match final_str { 
-			"show" => {
-				Some(Token::Printable(String::new()))
-			}
-			"lvalue" => {
-				Some(Token::Assignment(String::new()))
-			}
-			"rvalue" => {
-				Some(Token::Assignment(String::new()))
-			}
-			"push" => {
-				Some(Token::Assignment(String::new()))
-			}
-			"label" => {
-				Some(Token::FunctionName(String::new()))
-			}
-			"call" => {
-				Some(Token::FunctionCallWithParams(String::new()))
-			}
-			"goto" => {
-				Some(Token::FunctionCall(String::new()))
-			}
-			"gofalse" => {
-				Some(Token::GotoLabel(String::new()))
+	pub fn diagnostic(&self) -> Diagnostic {
+		let diagnostic = Diagnostic::error(self.message()).at(self.span);
+		match self.kind {
+			LexErrorKind::UnexpectedCharacter(_) => diagnostic.label("not part of the jaz language"),
+			LexErrorKind::UnterminatedOperator(_) => diagnostic.label("expected `:=`").help("assignment is written `:=`"),
+			LexErrorKind::IntegerOverflow(_) => diagnostic.label("too large").note(format!("the largest constant is {}", i64::MAX)),
+			LexErrorKind::UnknownKeyword(_) => diagnostic
+				.label("not a jaz instruction")
+				.help("variable and label names only follow the instruction that uses them, as in `rvalue x` or `goto loop`"),
+		}
+	}
+}
+
+impl fmt::Display for LexError {
+	fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+		write!(f, "{}: {}", self.span, self.message())
+	}
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Spanned<T> {
+	pub node: T,
+	pub span: Span,
+}
+
+/* Walks the source one character at a time, keeping track of the position */
+struct Cursor<'a> {
+	chars: Peekable<CharIndices<'a>>,
+	length: usize,
+	line: usize,
+	column: usize,
+}
+
+impl<'a> Cursor<'a> {
+	fn new(input: &'a str) -> Cursor<'a> {
+		Cursor { chars: input.char_indices().peekable(), length: input.len(), line: 1, column: 1 }
+	}
+
+	fn peek(&mut self) -> Option<char> {
+		self.chars.peek().map(|&(_, c)| c)
+	}
+
+	fn offset(&mut self) -> usize {
+		let length = self.length;
+		self.chars.peek().map_or(length, |&(offset, _)| offset)
+	}
+
+	fn next(&mut self) -> Option<char> {
+		let (_, c) = self.chars.next()?;
+		if c == '\n' {
+			self.line += 1;
+			self.column = 1;
+		} else {
+			self.column += 1;
+		}
+		Some(c)
+	}
+
+	/* Zero length span at the current position, grown by `finish` */
+	fn start(&mut self) -> Span {
+		Span { offset: self.offset(), line: self.line, column: self.column, length: 0 }
+	}
+
+	fn finish(&mut self, start: Span) -> Span {
+		Span { length: self.offset() - start.offset, ..start }
+	}
+
+	fn take_while<F: Fn(char) -> bool>(&mut self, accept: F) -> String {
+		let mut taken = String::new();
+		while let Some(c) = self.peek() {
+			if !accept(c) {
+				break;
 			}
+			taken.push(c);
+			self.next();
+		}
+		taken
+	}
+}
+
+impl Tokenizer {
+	/* The token an operand becomes depends on the keyword in front of it */
+	fn operand_token(keyword: &str) -> Option<fn(String) -> Token> {
+		match keyword {
+			"lvalue" | "rvalue" | "push" => Some(Token::Assignment),
+			"label" => Some(Token::FunctionName),
+			"call" => Some(Token::FunctionCallWithParams),
+			"goto" => Some(Token::FunctionCall),
+			"gofalse" | "gotrue" => Some(Token::GotoLabel),
 			_ => None,
-		};
-		match token.clone() {
-			Some(new) => {
-				return Recognize { 
-					token: token, 
-					to_match: true,
-					collection: Vec::new()
-				};
-			}
-			None => {
-				return Recognize {
-					token: None,
-					to_match: false,
-					collection: Vec::new()
-				}
-			}
 		}
 	}
 
-	pub fn lex(input: &String) -> Result<Vec<Token>, String>
+	/* Lexes the whole input, reporting every error found instead of the first */
+	pub fn lex(input: &str) -> Result<Vec<Spanned<Token>>, Vec<LexError>>
 	{
-		/* This allows us to recognize any token, and collect its subsequent values */
-		let mut recognizer = Recognize { 
-			token: None, 
-			to_match: false,
-			collection: Vec::new()
-		};
-
 		/* Populate HashSet with allowable keywords */
 		let grammar: HashSet<&str> = [
 			"push", "rvalue", "lvalue", "pop",
@@ -145,469 +194,163 @@ impl Tokenizer {
 			"return", "call",
 		].iter().cloned().collect();
 
-		let mut result: Vec<Token> = Vec::new();
-		let mut iterator = input.chars().peekable();
-		let mut token_buf: Vec<String> = Vec::new();
-
-		/* If we can peek, we peek */
-		let mut is_printable: bool = false;
-		while let Some(&raw) = iterator.peek() {
-			match raw {
-				'a' ... 'z' => { /* Match a-z characters */
-
-					while iterator.peek().unwrap().is_alphabetic() {
-						let val = iterator.next().unwrap().to_string();
-
-						if recognizer.to_match {
-							recognizer.collection.push(val.to_string());
-						}
-
-						token_buf.push(val);
-					}
-
-					let final_str: String = token_buf.join("");
-					/* Empty out token buffer */
-					
-					if !is_printable {
-						if grammar.contains(final_str.deref()) {
-							result.push(Token::Keyword(final_str.clone()));
-
-							let old_token = recognizer.token;
-							recognizer = Tokenizer::build_recognizer(
-								&*final_str,
-								old_token.clone(),
-								&mut true
-							);
-							if let Some(Token::Printable(a)) = recognizer.token.clone() {
-								is_printable = true;
-							}							
-						}
-					}
-					token_buf.clear();
-				}
-
-				'\n' => {
-					is_printable = false;
-					Tokenizer::reduce_tokens_to_enum(&mut recognizer, &mut result);
-					recognizer.token = None;
-					iterator.next();
-				}
+		let mut result: Vec<Spanned<Token>> = Vec::new();
+		let mut errors: Vec<LexError> = Vec::new();
+		let mut cursor = Cursor::new(input);
 
-				'\r' => {
-					// println!("HIT...");
-					let val = iterator.next().unwrap();
-					
-					match recognizer.token {
-						Some(Token::Printable(ref c)) => {
-							if val != '\r' || val != '\n' {
-								recognizer.collection.push(val.to_string());
-							}						
-						}
-						Some(Token::Assignment(ref c)) => {
-							if val != ' ' {
-								recognizer.collection.push(val.to_string());
-							}
-						}
-						Some(Token::FunctionCall(ref c)) => {
-							if val != ' ' {
-								recognizer.collection.push(val.to_string());
-							}
-						}
-						Some(Token::FunctionCallWithParams(ref c)) => {
-							if val != ' ' {
-								recognizer.collection.push(val.to_string());
-							}					
-						}
-						Some(Token::FunctionName(ref c)) => {
-							if val != ' ' { /* Can further validate syntax here */
-								recognizer.collection.push(val.to_string());
-							}
-						}
-						Some(Token::GotoLabel(ref c)) => {
-							if val != ' ' {
-								recognizer.collection.push(val.to_string());
-							}
-						}
-						None => { /* Recognizer did not handle Token type */ }
-						_ => {}
-					}
-				}
+		/* Set by a keyword that takes an operand, this recognizes the word after it */
+		let mut recognizer: Option<fn(String) -> Token> = None;
 
+		while let Some(raw) = cursor.peek() {
+			let start = cursor.start();
+			let token = match raw {
 				'\n' => {
-					let val = iterator.next().unwrap();
-					match recognizer.token {
-						Some(Token::Printable(ref c)) => {
-							if val != '\r' || val != '\n' {
-								recognizer.collection.push(val.to_string());
-							}					
-						}
-						Some(Token::Assignment(ref c)) => {
-							if val != ' ' {
-								recognizer.collection.push(val.to_string());
-							}
-						}
-						Some(Token::FunctionCall(ref c)) => {
-							if val != ' ' {
-								recognizer.collection.push(val.to_string());
-							}
-						}
-						Some(Token::FunctionCallWithParams(ref c)) => {
-							if val != ' ' {
-								recognizer.collection.push(val.to_string());
-							}					
-						}
-						Some(Token::FunctionName(ref c)) => {
-							if val != ' ' { 
-								recognizer.collection.push(val.to_string());
-							}
-						}
-						Some(Token::GotoLabel(ref c)) => {
-							if val != ' ' {
-								recognizer.collection.push(val.to_string());
-							}
-						}
-						None => { /* Recognizer did not handle Token type */  }
-						_ => {}
-					}
-				}
-
-				' ' => {
-					let val = iterator.next().unwrap();
-					match recognizer.token {
-						Some(Token::Printable(ref c)) => {
-							if val != '\r' || val != '\n' {
-								recognizer.collection.push(val.to_string());
-							}					
-						}
-						Some(Token::Assignment(ref c)) => {
-							if val != ' ' {
-								recognizer.collection.push(val.to_string());
-							}
-						}
-						Some(Token::FunctionCall(ref c)) => {
-							if val != ' ' {
-								recognizer.collection.push(val.to_string());
-							}
-						}
-						Some(Token::FunctionCallWithParams(ref c)) => {
-							if val != ' ' {
-								recognizer.collection.push(val.to_string());
-							}					
-						}
-						Some(Token::FunctionName(ref c)) => {
-							if val != ' ' { 
-								recognizer.collection.push(val.to_string());
-							}
-						}
-						Some(Token::GotoLabel(ref c)) => {
-							if val != ' ' {
-								recognizer.collection.push(val.to_string());
-							}
-						}
-						None => { /* Recognizer did not handle Token type */ }
-						_ => {}
-					}
+					cursor.next();
+					recognizer = None;
+					continue;
 				}
-				'+' => {
-					if let Some(Token::Printable(ref apple)) = recognizer.token {
-						/* If we are matching printable tokens, ignore operators */
-						let val = iterator.next().unwrap();
-
-						match recognizer.token {
-							Some(Token::Printable(ref c)) => {
-								// can_update_recognizer = false;
-								if val != '\r' || val != '\n' {
-									recognizer.collection.push(val.to_string());
-								}
-							}
-							_ => {}
-						}
-					} else {
-						iterator.next();
-						result.push(Token::Plus);
-					}
+				' ' | '\t' | '\r' => {
+					cursor.next();
+					continue;
 				}
-				'-' => {
-					if let Some(Token::Printable(ref apple)) = recognizer.token {
-						/* If we are matching printable tokens, ignore operators */
-						let val = iterator.next().unwrap();
-						match recognizer.token {
-							Some(Token::Printable(ref c)) => {
-								if val != '\r' || val != '\n' {
-									recognizer.collection.push(val.to_string());
-								}
-							}
-							_ => {
-								result.push(Token::Minus);
-							}
-						}
-					} else {
-						/* Otherwise match the operators token value */
-						match iterator.clone().peek() {
-							Some(peeked) => {
-								if *peeked != '-' {
-									println!("Not Peeked...");
-									result.push(Token::Minus);
-									iterator.next();
-								} else {
-									iterator.next();
-								}
-							}
-							None => {
-								println!("Peeked...");
-							}
+				'0' ..= '9' => {
+					let digits = cursor.take_while(|c| c.is_ascii_digit());
+					recognizer = None;
+					match digits.parse::<i64>() {
+						Ok(num) => Token::Constant(num),
+						Err(_) => {
+							let span = cursor.finish(start);
+							errors.push(LexError { kind: LexErrorKind::IntegerOverflow(digits), span });
+							continue;
 						}
 					}
 				}
-				'/' => {
-					if let Some(Token::Printable(ref apple)) = recognizer.token {
-						/* If we are matching printable tokens, ignore operators */
-						let val = iterator.next().unwrap();
-						match recognizer.token {
-							Some(Token::Printable(ref c)) => {
-								// can_update_recognizer = false;
-								if val != '\r' || val != '\n' {
-									recognizer.collection.push(val.to_string());
-								}
-							}
-							_ => {
-								result.push(Token::IntDiv);
-							}
-						}
+				c if c.is_alphabetic() => {
+					let word = cursor.take_while(|c| c.is_alphanumeric() || c == '_');
+					if let Some(operand) = recognizer.take() {
+						operand(word)
+					} else if grammar.contains(word.as_str()) {
+						recognizer = Tokenizer::operand_token(&word);
+						if word == "show" {
+							result.push(Spanned { node: Token::Keyword(word), span: cursor.finish(start) });
+
+							/* Everything up to the end of the line is printed, minus one separating space */
+							if let Some(' ') | Some('\t') = cursor.peek() {
+								cursor.next();
+							}
+							let text_start = cursor.start();
+							let text = cursor.take_while(|c| c != '\n');
+							let text = text.strip_suffix('\r').unwrap_or(&text).to_string();
+							let mut span = cursor.finish(text_start);
+							span.length = text.len();
+							result.push(Spanned { node: Token::Printable(text), span });
+							continue;
+						}
+						Token::Keyword(word)
 					} else {
-						iterator.next();
-						result.push(Token::IntDiv);
+						let span = cursor.finish(start);
+						errors.push(LexError { kind: LexErrorKind::UnknownKeyword(word), span });
+						continue;
 					}
 				}
-				'&' => {
-					if let Some(Token::Printable(ref apple)) = recognizer.token {
-						/* If we are matching printable tokens, ignore operators */
-						let val = iterator.next().unwrap();
-
-						match recognizer.token {
-							Some(Token::Printable(ref c)) => {
-								// can_update_recognizer = false;
-								if val != '\r' || val != '\n' {
-									recognizer.collection.push(val.to_string());
+				_ => {
+					cursor.next();
+					match raw {
+						'+' => Token::Plus,
+						'-' => Token::Minus,
+						'*' => Token::Multiply,
+						'/' => Token::IntDiv,
+						'&' => Token::And,
+						'|' => Token::Or,
+						'!' => Token::Not,
+						'=' => Token::Equal,
+						'<' => {
+							match cursor.peek() {
+								Some('>') => {
+									cursor.next();
+									Token::Equivalent
 								}
-							}
-							_ => {
-								result.push(Token::And);
-							}
-						}
-					} else {
-						iterator.next();
-						result.push(Token::And);
-					}
-				}
-				'!' => {
-					if let Some(Token::Printable(ref apple)) = recognizer.token {
-						/* If we are matching printable tokens, ignore operators */
-						let val = iterator.next().unwrap();
-
-						match recognizer.token {
-							Some(Token::Printable(ref c)) => {
-								// can_update_recognizer = false;
-								if val != '\r' || val != '\n' {
-									recognizer.collection.push(val.to_string());
+								Some('=') => {
+									cursor.next();
+									Token::LessThanEqual
 								}
-							}
-							_ => {
-								result.push(Token::Not);
+								_ => Token::LessThan,
 							}
 						}
-					} else {
-						iterator.next();
-						result.push(Token::Not);
-					}
-				}
-				'*' => {
-					if let Some(Token::Printable(ref apple)) = recognizer.token {
-						/* If we are matching printable tokens, ignore operators */
-						let val = iterator.next().unwrap();
-
-						match recognizer.token {
-							Some(Token::Printable(ref c)) => {
-								// can_update_recognizer = false;
-								if val != '\r' || val != '\n' {
-									recognizer.collection.push(val.to_string());
-								}
-							}
-							_ => {
-								result.push(Token::Multiply);
+						'>' => {
+							if cursor.peek() == Some('=') {
+								cursor.next();
+								Token::GreaterThanEqual
+							} else {
+								Token::GreaterThan
 							}
 						}
-					} else {
-						iterator.next();
-						result.push(Token::Multiply);
-					}
-				}
-				'|' => {
-					if let Some(Token::Printable(ref apple)) = recognizer.token {
-						/* If we are matching printable tokens, ignore operators */
-						let val = iterator.next().unwrap();
-
-						match recognizer.token {
-							Some(Token::Printable(ref c)) => {
-								// can_update_recognizer = false;
-								if val != '\r' || val != '\n' {
-									recognizer.collection.push(val.to_string());
-								}
-							}
-							_ => {
-								result.push(Token::Or);
+						':' => {
+							if cursor.peek() != Some('=') {
+								let span = cursor.finish(start);
+								errors.push(LexError { kind: LexErrorKind::UnterminatedOperator(raw), span });
+								continue;
 							}
+							cursor.next();
+							Token::Assignment(":=".to_string())
 						}
-					} else {
-						iterator.next();
-						result.push(Token::Or);
-					}
-				}
-				'<' => {
-					if let Some(Token::Printable(ref apple)) = recognizer.token {
-						/* If we are matching printable tokens, ignore operators */
-						let val = iterator.next().unwrap();
-
-						match recognizer.token {
-							Some(Token::Printable(ref c)) => {
-								// can_update_recognizer = false;
-								if val != '\r' || val != '\n' {
-									recognizer.collection.push(val.to_string());
-								}
-							}
-							_ => {}
-						}
-					} else {
-						match iterator.clone().peek() {
-							Some(peeked) => {
-								if *peeked == '>' {
-									result.push(Token::Equivalent);
-									iterator.next();
-								} else if *peeked == '=' {
-									result.push(Token::LessThanEqual);
-									iterator.next();
-								} else {
-									result.push(Token::LessThan);
-									iterator.next();
-								}
-							}
-							None => {
-								println!("Couldnt peek.");
-							}
+						_ => {
+							let span = cursor.finish(start);
+							errors.push(LexError { kind: LexErrorKind::UnexpectedCharacter(raw), span });
+							continue;
 						}
 					}
 				}
-				'>' => {
-					if let Some(Token::Printable(ref apple)) = recognizer.token {
-						/* If we are matching printable tokens, ignore operators */
-						let val = iterator.next().unwrap();
-						match recognizer.token {
-							Some(Token::Printable(ref c)) => {
-								// can_update_recognizer = false;
-								if val != '\r' || val != '\n' {
-									recognizer.collection.push(val.to_string());
-								}
-							}
-							_ => {}
-						}
-					} else {
-						match iterator.clone().peek() {
-							Some(peeked) => {
-								if *peeked == '=' {
-									result.push(Token::GreaterThanEqual);
-									iterator.next();
-								} else {
-									result.push(Token::GreaterThan);
-									iterator.next();
-								}
-							}
+			};
+			result.push(Spanned { node: token, span: cursor.finish(start) });
+		}
 
-							None => {
-								println!("Couldnt Peek.");
-							}
-						}
-					}
-				}
-				'=' => {
-					if let Some(Token::Printable(ref apple)) = recognizer.token {
-						/* If we are matching printable tokens, ignore operators */
-						let val = iterator.next().unwrap();
-						match recognizer.token {
-							Some(Token::Printable(ref c)) => {
-								// can_update_recognizer = false;
-								if val != '\r' || val != '\n' {
-									recognizer.collection.push(val.to_string());
-								}
-							}
-							_ => {}
-						}
-					} else {
-						result.push(Token::Equal);
-						iterator.next();
-					}
-				}
-				':' => {
-					if let Some(Token::Printable(ref apple)) = recognizer.token {
-						/* If we are matching printable tokens, ignore operators */
-						let val = iterator.next().unwrap();
-
-						match recognizer.token {
-							Some(Token::Printable(ref c)) => {
-								// can_update_recognizer = false;
-								recognizer.collection.push(val.to_string());
-							}
-							_ => {}
-						}
-					} else {
-						let a = iterator.next().unwrap().to_string();
-						if *iterator.peek().unwrap() == '=' {
-							let b = iterator.next().unwrap().to_string();
-							result.push(Token::Assignment([a, b].join("")));
-						}
-					}
-				}
-				'0' ... '9' => { /* Match 0-9 characters */
-					if let Some(Token::Printable(ref apple)) = recognizer.token {
-						/* If we are matching printable tokens, ignore operators */
-						let val = iterator.next().unwrap();
-
-						match recognizer.token {
-							Some(Token::Printable(ref c)) => {
-								// can_update_recognizer = false;
-								recognizer.collection.push(val.to_string());
-							}
-							_ => {}
-						}
-					} else {
-						iterator.next(); /* Consume Next Character */
+		if errors.is_empty() {
+			Ok(result)
+		} else {
+			Err(errors)
+		}
+	}
+}
 
-						let mut num = raw.to_string().parse::<i64>().expect("");
+#[cfg(test)]
+mod tests {
+	use super::{LexError, LexErrorKind, Span, Tokenizer};
 
-						while let Ok(digit) = iterator.peek().map(|c| c.to_string().parse::<i64>()).unwrap() {
-							num = num * 10 + digit;
-							iterator.next();
-						}
+	fn errors(source: &str) -> Vec<LexError> {
+		Tokenizer::lex(source).unwrap_err()
+	}
 
-						result.push(Token::Constant(num));
-					}
-				},
-				/* Handle all other cases, debug */
-				_ => {
+	fn span(offset: usize, line: usize, column: usize, length: usize) -> Span {
+		Span { offset, line, column, length }
+	}
 
-					let val = iterator.next().unwrap();
+	#[test]
+	fn lexes_valid_programs() {
+		assert!(Tokenizer::lex(include_str!("../factProc.jaz")).is_ok());
+		assert!(Tokenizer::lex(include_str!("../operatorsTest.jaz")).is_ok());
+	}
 
-					match recognizer.token {
-						Some(Token::Printable(ref c)) => {
-							// can_update_recognizer = false;
-							if val != '\r' || val != '\n' {
-								recognizer.collection.push(val.to_string());
-							}
-						}
-						_ => {}
-					}
-				}
-			}
-		}
-		Ok(result)
+	#[test]
+	fn reports_each_kind_where_it_is() {
+		assert_eq!(errors("push 1\npush $\n"), vec![LexError { kind: LexErrorKind::UnexpectedCharacter('$'), span: span(12, 2, 6, 1) }]);
+		assert_eq!(errors("lvalue x\npush 1\n:\n"), vec![LexError { kind: LexErrorKind::UnterminatedOperator(':'), span: span(16, 3, 1, 1) }]);
+		let big = "9223372036854775808";
+		assert_eq!(errors(&format!("push {}\n", big)), vec![LexError { kind: LexErrorKind::IntegerOverflow(big.to_string()), span: span(5, 1, 6, big.len()) }]);
+		assert_eq!(errors("halt\n  jump\n"), vec![LexError { kind: LexErrorKind::UnknownKeyword("jump".to_string()), span: span(7, 2, 3, 4) }]);
 	}
-}
\ No newline at end of file
+
+	#[test]
+	fn reports_every_error_in_one_run() {
+		let found = errors("push $\nfoo\npush 99999999999999999999\nlvalue x\n:\nhalt\n");
+		let kinds: Vec<LexErrorKind> = found.iter().map(|error| error.kind.clone()).collect();
+		assert_eq!(kinds, vec![
+			LexErrorKind::UnexpectedCharacter('$'),
+			LexErrorKind::UnknownKeyword("foo".to_string()),
+			LexErrorKind::IntegerOverflow("99999999999999999999".to_string()),
+			LexErrorKind::UnterminatedOperator(':'),
+		]);
+		let lines: Vec<usize> = found.iter().map(|error| error.span.line).collect();
+		assert_eq!(lines, vec![1, 2, 3, 5]);
+	}
+}